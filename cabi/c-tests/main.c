@@ -13,6 +13,7 @@ static void print_header(MementoHeaderResult *res) {
     printf("Aggregation: %u\n", res->header->metadata.aggregation);
     printf("Max retention: %u\n", res->header->metadata.max_retention);
     printf("X Files Factor: %f\n", res->header->metadata.x_files_factor);
+    printf("Retention: %s\n", res->header->retention);
 
     for (int i = 0; i < res->header->size; i++) {
         printf("Archive %i\n", i);
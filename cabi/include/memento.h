@@ -29,6 +29,15 @@ enum MementoErrorCode {
     InvalidTimeEnd = 1005,
     NoArchiveAvailable = 1006,
     CorruptDatabase = 1007,
+    InvalidRetention = 1008,
+    EmptyArchiveList = 1009,
+    DuplicateArchivePrecision = 1010,
+    UnsortedArchives = 1011,
+    UnevenArchivePrecision = 1012,
+    InsufficientArchiveRetention = 1013,
+    InsufficientArchivePoints = 1014,
+    LockUnavailable = 1015,
+    InvalidXFilesFactor = 1016,
 };
 typedef uint32_t MementoErrorCode;
 
@@ -49,6 +58,7 @@ typedef struct {
     MementoMetadata metadata;
     MementoArchiveInfo *archives;
     size_t size;
+    char *retention;
 } MementoHeader;
 
 typedef struct {
@@ -23,6 +23,15 @@ pub enum MementoErrorCode {
     InvalidTimeEnd = 1005,
     NoArchiveAvailable = 1006,
     CorruptDatabase = 1007,
+    InvalidRetention = 1008,
+    EmptyArchiveList = 1009,
+    DuplicateArchivePrecision = 1010,
+    UnsortedArchives = 1011,
+    UnevenArchivePrecision = 1012,
+    InsufficientArchiveRetention = 1013,
+    InsufficientArchivePoints = 1014,
+    LockUnavailable = 1015,
+    InvalidXFilesFactor = 1016,
 }
 
 impl MementoErrorCode {
@@ -41,6 +50,15 @@ impl From<ErrorKind> for MementoErrorCode {
             ErrorKind::InvalidTimeEnd => MementoErrorCode::InvalidTimeEnd,
             ErrorKind::NoArchiveAvailable => MementoErrorCode::NoArchiveAvailable,
             ErrorKind::CorruptDatabase => MementoErrorCode::CorruptDatabase,
+            ErrorKind::InvalidRetention => MementoErrorCode::InvalidRetention,
+            ErrorKind::EmptyArchiveList => MementoErrorCode::EmptyArchiveList,
+            ErrorKind::DuplicateArchivePrecision => MementoErrorCode::DuplicateArchivePrecision,
+            ErrorKind::UnsortedArchives => MementoErrorCode::UnsortedArchives,
+            ErrorKind::UnevenArchivePrecision => MementoErrorCode::UnevenArchivePrecision,
+            ErrorKind::InsufficientArchiveRetention => MementoErrorCode::InsufficientArchiveRetention,
+            ErrorKind::InsufficientArchivePoints => MementoErrorCode::InsufficientArchivePoints,
+            ErrorKind::LockUnavailable => MementoErrorCode::LockUnavailable,
+            ErrorKind::InvalidXFilesFactor => MementoErrorCode::InvalidXFilesFactor,
         }
     }
 }
@@ -57,6 +75,15 @@ impl fmt::Display for MementoErrorCode {
             MementoErrorCode::InvalidTimeEnd => "invalid time end",
             MementoErrorCode::NoArchiveAvailable => "no archive available",
             MementoErrorCode::CorruptDatabase => "corrupt database",
+            MementoErrorCode::InvalidRetention => "invalid retention",
+            MementoErrorCode::EmptyArchiveList => "empty archive list",
+            MementoErrorCode::DuplicateArchivePrecision => "duplicate archive precision",
+            MementoErrorCode::UnsortedArchives => "unsorted archives",
+            MementoErrorCode::UnevenArchivePrecision => "uneven archive precision",
+            MementoErrorCode::InsufficientArchiveRetention => "insufficient archive retention",
+            MementoErrorCode::InsufficientArchivePoints => "insufficient archive points",
+            MementoErrorCode::LockUnavailable => "lock unavailable",
+            MementoErrorCode::InvalidXFilesFactor => "invalid x-files-factor",
         };
 
         write!(f, "{}", msg)
@@ -10,10 +10,11 @@
 
 use std::mem;
 use std::ptr;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
 use memento::MementoFileReader;
 use memento::errors::ErrorKind;
+use memento::retention::format_retentions;
 use memento::types::{AggregationType, ArchiveInfo, Header, Metadata};
 use common::MementoErrorCode;
 
@@ -59,6 +60,7 @@ pub struct MementoHeader {
     pub metadata: MementoMetadata,
     pub archives: *mut MementoArchiveInfo,
     pub size: usize,
+    pub retention: *mut c_char,
 }
 
 impl From<Header> for MementoHeader {
@@ -70,11 +72,17 @@ impl From<Header> for MementoHeader {
             .map(|i| MementoArchiveInfo::from(i.clone()))
             .collect();
 
+        // Retention definitions only ever contain digits, units, and separators
+        // so there's no way for them to contain a null byte.
+        let retention = CString::new(format_retentions(header.archive_info()))
+            .expect("unexpected null byte in retention definition");
+
         archives.shrink_to_fit();
         let out = MementoHeader {
             metadata: meta,
             archives: (&mut archives).as_mut_ptr(),
             size: archives.len(),
+            retention: retention.into_raw(),
         };
         mem::forget(archives);
         out
@@ -93,6 +101,13 @@ impl Drop for MementoHeader {
                 );
             }
         }
+
+        if !self.retention.is_null() {
+            unsafe {
+                // Convert back into a Rust type to free the memory
+                drop(CString::from_raw(self.retention));
+            }
+        }
     }
 }
 
@@ -0,0 +1,124 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Daemon that accepts the Graphite plaintext protocol and writes points
+//! to Whisper files
+
+#[macro_use]
+extern crate clap;
+extern crate memento;
+
+use std::process;
+
+use clap::{App, Arg, ArgMatches};
+
+use memento::carbon::{CarbonServer, MetricStore};
+use memento::errors::MementoResult;
+use memento::retention::parse_retentions;
+use memento::types::{AggregationType, HeaderBuilder};
+
+// Exit code used when the daemon fails to start or stops with an error,
+// matching the `memento` command.
+const EXIT_ERROR: i32 = 2;
+
+fn run(args: &ArgMatches) -> MementoResult<()> {
+    let header = HeaderBuilder::new()
+        .with_archives(parse_retentions(args.value_of("schema").unwrap())?)
+        .with_aggregation(args.value_of("aggregation").unwrap().parse()?)
+        .with_x_files_factor(value_t!(args, "x-files-factor", f32).unwrap_or_else(|e| e.exit()))
+        .build();
+
+    let store = MetricStore::new(args.value_of("ROOT").unwrap(), &header)?;
+    let mut server = CarbonServer::new(store)
+        .with_max_connections(value_t!(args, "max-connections", usize).unwrap_or_else(|e| e.exit()))
+        .with_error_handler(|e| eprintln!("memento-carbon: {}", e));
+
+    let addr = server.listen_tcp(args.value_of("tcp").unwrap())?;
+    eprintln!("memento-carbon: listening on tcp://{}", addr);
+
+    if let Some(udp) = args.value_of("udp") {
+        let addr = server.listen_udp(udp)?;
+        eprintln!("memento-carbon: listening on udp://{}", addr);
+    }
+
+    server.run()
+}
+
+fn main() {
+    let methods: Vec<&str> = AggregationType::ALL.iter().map(|m| m.name()).collect();
+    let matches = App::new("memento-carbon")
+        .version(crate_version!())
+        .about("Accept the Graphite plaintext protocol and write points to Whisper files")
+        .after_help(
+            "Each metric is written to a file under ROOT with each dot separated part \
+             of its name as a directory, e.g. foo.bar.baz is written to \
+             ROOT/foo/bar/baz.wsp. Files that don't exist are created using the given \
+             schema, aggregation method, and x-files-factor.",
+        )
+        .arg(
+            Arg::with_name("ROOT")
+                .required(true)
+                .help("Directory to write Whisper files under"),
+        )
+        .arg(
+            Arg::with_name("schema")
+                .long("schema")
+                .takes_value(true)
+                .value_name("RETENTIONS")
+                .default_value("1m:1d")
+                .help("Archives for new files, e.g. 10s:1d,1m:7d,1h:5y"),
+        )
+        .arg(
+            Arg::with_name("aggregation")
+                .long("aggregation")
+                .takes_value(true)
+                .value_name("METHOD")
+                .possible_values(&methods)
+                .default_value("average")
+                .help("Aggregation method for new files"),
+        )
+        .arg(
+            Arg::with_name("x-files-factor")
+                .long("x-files-factor")
+                .takes_value(true)
+                .value_name("FACTOR")
+                .default_value("0.5")
+                .help("X-files-factor for new files"),
+        )
+        .arg(
+            Arg::with_name("tcp")
+                .long("tcp")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .default_value("0.0.0.0:2003")
+                .help("Address to accept TCP connections on"),
+        )
+        .arg(
+            Arg::with_name("udp")
+                .long("udp")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .help("Address to accept UDP datagrams on (default: disabled)"),
+        )
+        .arg(
+            Arg::with_name("max-connections")
+                .long("max-connections")
+                .takes_value(true)
+                .value_name("NUM")
+                .default_value("1024")
+                .help("Number of TCP connections to handle at once"),
+        )
+        .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("memento-carbon: {}", e);
+        process::exit(EXIT_ERROR);
+    }
+}
@@ -0,0 +1,305 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Check Whisper files, or directories of them, for problems
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde_json::Value;
+
+use memento::check::{CheckReport, Problem};
+use memento::errors::MementoResult;
+use memento::MementoFileReader;
+
+use output::{json_arg, write_json};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("check")
+        .about("Check Whisper files for problems without trusting their contents")
+        .after_help(
+            "Directories are searched for files with a .wsp extension. Exits with \
+             status 0 if no problems were found, 1 if there were problems with any \
+             file or any file could not be read, and 2 if a directory could not be \
+             read.",
+        )
+        .arg(
+            Arg::with_name("PATH")
+                .required(true)
+                .multiple(true)
+                .help("Whisper file or directory"),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .takes_value(true)
+                .value_name("NUM")
+                .help("Number of files to check at once (default: number of CPUs)"),
+        )
+        .arg(json_arg())
+}
+
+pub fn run(args: &ArgMatches) -> MementoResult<i32> {
+    let mut reader = MementoFileReader::new();
+    if args.is_present("threads") {
+        reader = reader.with_threads(value_t!(args, "threads", usize).unwrap_or_else(|e| e.exit()));
+    }
+
+    let mut results = Vec::new();
+    let mut files = Vec::new();
+
+    for path in args.values_of("PATH").unwrap().map(Path::new) {
+        if path.is_dir() {
+            results.extend(reader.check_tree(path)?);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    let reports = reader.check_many(&files);
+    results.extend(files.into_iter().zip(reports));
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    if args.is_present("json") {
+        write_json(&mut out, &results_to_json(&results))?;
+    } else {
+        write_results(&mut out, &results)?;
+    }
+
+    let ok = results
+        .iter()
+        .all(|(_, res)| res.as_ref().map(|r| r.is_ok()).unwrap_or(false));
+    Ok(if ok { 0 } else { 1 })
+}
+
+/// Convert a problem to JSON with a `type` field naming the problem and
+/// a field for each of its values.
+fn problem_to_json(problem: &Problem) -> Value {
+    let message = problem.to_string();
+    match *problem {
+        Problem::TruncatedHeader { expected, actual } => json!({
+            "type": "truncatedHeader",
+            "expected": expected,
+            "actual": actual,
+            "message": message,
+        }),
+        Problem::UnknownAggregation(code) => json!({
+            "type": "unknownAggregation",
+            "actual": code,
+            "message": message,
+        }),
+        Problem::InvalidXFilesFactor(xff) => json!({
+            "type": "invalidXFilesFactor",
+            "actual": xff,
+            "message": message,
+        }),
+        Problem::InvalidArchives(_, _) => json!({
+            "type": "invalidArchives",
+            "message": message,
+        }),
+        Problem::MaxRetention { expected, actual } => json!({
+            "type": "maxRetention",
+            "expected": expected,
+            "actual": actual,
+            "message": message,
+        }),
+        Problem::ArchiveOffset {
+            archive,
+            expected,
+            actual,
+        } => json!({
+            "type": "archiveOffset",
+            "archive": archive,
+            "expected": expected,
+            "actual": actual,
+            "message": message,
+        }),
+        Problem::FileSize { expected, actual } => json!({
+            "type": "fileSize",
+            "expected": expected,
+            "actual": actual,
+            "message": message,
+        }),
+        Problem::UnalignedPoint {
+            archive,
+            index,
+            timestamp,
+        } => json!({
+            "type": "unalignedPoint",
+            "archive": archive,
+            "index": index,
+            "timestamp": timestamp,
+            "message": message,
+        }),
+        Problem::FuturePoint {
+            archive,
+            index,
+            timestamp,
+        } => json!({
+            "type": "futurePoint",
+            "archive": archive,
+            "index": index,
+            "timestamp": timestamp,
+            "message": message,
+        }),
+        Problem::ExpiredPoint {
+            archive,
+            index,
+            timestamp,
+        } => json!({
+            "type": "expiredPoint",
+            "archive": archive,
+            "index": index,
+            "timestamp": timestamp,
+            "message": message,
+        }),
+        Problem::NanValue {
+            archive,
+            index,
+            timestamp,
+        } => json!({
+            "type": "nanValue",
+            "archive": archive,
+            "index": index,
+            "timestamp": timestamp,
+            "message": message,
+        }),
+    }
+}
+
+/// Convert the result of checking each file to JSON. Files that could not
+/// be read have an `error` instead of a list of problems.
+fn results_to_json(results: &[(PathBuf, MementoResult<CheckReport>)]) -> Value {
+    let files: Vec<Value> = results
+        .iter()
+        .map(|(path, res)| match *res {
+            Ok(ref report) => json!({
+                "path": path.display().to_string(),
+                "ok": report.is_ok(),
+                "problems": report.problems().iter().map(problem_to_json).collect::<Vec<_>>(),
+            }),
+            Err(ref e) => json!({
+                "path": path.display().to_string(),
+                "ok": false,
+                "error": e.to_string(),
+            }),
+        })
+        .collect();
+
+    json!({ "files": files })
+}
+
+/// Write each problem, or error reading a file, on its own line prefixed
+/// by the path of the file. Nothing is written for files without problems.
+fn write_results<W>(
+    writer: &mut W,
+    results: &[(PathBuf, MementoResult<CheckReport>)],
+) -> io::Result<()>
+where
+    W: Write,
+{
+    for (path, res) in results {
+        match *res {
+            Ok(ref report) => {
+                for problem in report.problems() {
+                    writeln!(writer, "{}: {}", path.display(), problem)?;
+                }
+            }
+            Err(ref e) => writeln!(writer, "{}: error: {}", path.display(), e)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use memento::check::{CheckReport, Problem};
+    use memento::errors::{ErrorKind, MementoError, MementoResult};
+
+    use super::{results_to_json, write_results};
+
+    fn get_results() -> Vec<(PathBuf, MementoResult<CheckReport>)> {
+        vec![
+            (PathBuf::from("a.wsp"), Ok(CheckReport::default())),
+            (
+                PathBuf::from("b.wsp"),
+                Ok(CheckReport::new(vec![
+                    Problem::FileSize {
+                        expected: 100,
+                        actual: 88,
+                    },
+                    Problem::NanValue {
+                        archive: 1,
+                        index: 2,
+                        timestamp: 1500000000,
+                    },
+                ])),
+            ),
+            (
+                PathBuf::from("c.wsp"),
+                Err(MementoError::from((
+                    ErrorKind::LockUnavailable,
+                    "unable to acquire lock",
+                ))),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_write_results() {
+        let mut buf = vec![];
+        write_results(&mut buf, &get_results()).unwrap();
+
+        assert_eq!(
+            "b.wsp: file size: expected 100 bytes, found 88\n\
+             b.wsp: archive 1 point 2: NaN value at 1500000000\n\
+             c.wsp: error: unable to acquire lock\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_results_to_json() {
+        assert_eq!(
+            json!({
+                "files": [
+                    {"path": "a.wsp", "ok": true, "problems": []},
+                    {
+                        "path": "b.wsp",
+                        "ok": false,
+                        "problems": [
+                            {
+                                "type": "fileSize",
+                                "expected": 100,
+                                "actual": 88,
+                                "message": "file size: expected 100 bytes, found 88",
+                            },
+                            {
+                                "type": "nanValue",
+                                "archive": 1,
+                                "index": 2,
+                                "timestamp": 1500000000,
+                                "message": "archive 1 point 2: NaN value at 1500000000",
+                            },
+                        ],
+                    },
+                    {"path": "c.wsp", "ok": false, "error": "unable to acquire lock"},
+                ],
+            }),
+            results_to_json(&get_results())
+        );
+    }
+}
@@ -0,0 +1,213 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Compare two Whisper files archive by archive
+
+use std::io::{self, Write};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde_json::Value;
+
+use memento::diff::{DatabaseDiff, HeaderDiff};
+use memento::errors::MementoResult;
+use memento::retention::format_retention;
+use memento::types::ArchiveInfo;
+use memento::MementoFileReader;
+
+use output::{archive_to_json, json_arg, write_json};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("diff")
+        .about("Compare two Whisper files and print any differences")
+        .after_help(
+            "Exits with status 0 if the files are the same, 1 if they differ, \
+             and 2 if there was an error reading them.",
+        )
+        .arg(Arg::with_name("FIRST").required(true).help("First file"))
+        .arg(Arg::with_name("SECOND").required(true).help("Second file"))
+        .arg(json_arg())
+}
+
+pub fn run(args: &ArgMatches) -> MementoResult<i32> {
+    let first = args.value_of("FIRST").unwrap();
+    let second = args.value_of("SECOND").unwrap();
+
+    let reader = MementoFileReader::new();
+    let diff = reader.diff(first, second)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    if args.is_present("json") {
+        write_json(&mut out, &diff_to_json(&diff))?;
+    } else {
+        write_diff(&mut out, &diff)?;
+    }
+
+    Ok(if diff.is_empty() { 0 } else { 1 })
+}
+
+fn format_archive(info: &Option<ArchiveInfo>) -> String {
+    match *info {
+        Some(ref info) => format_retention(info),
+        None => "(none)".to_owned(),
+    }
+}
+
+fn format_value(val: Option<f64>) -> String {
+    match val {
+        Some(v) => v.to_string(),
+        None => "(none)".to_owned(),
+    }
+}
+
+/// Convert header differences and point differences to JSON, using `null`
+/// for archives or values that only exist in one of the files.
+fn diff_to_json(diff: &DatabaseDiff) -> Value {
+    let header: Vec<Value> = diff
+        .header()
+        .iter()
+        .map(|header| match *header {
+            HeaderDiff::Aggregation(a, b) => json!({
+                "field": "aggregationMethod",
+                "first": a.to_string(),
+                "second": b.to_string(),
+            }),
+            HeaderDiff::XFilesFactor(a, b) => json!({
+                "field": "xFilesFactor",
+                "first": a,
+                "second": b,
+            }),
+            HeaderDiff::Archive(i, ref a, ref b) => json!({
+                "field": "archive",
+                "archive": i,
+                "first": a.as_ref().map(archive_to_json),
+                "second": b.as_ref().map(archive_to_json),
+            }),
+        })
+        .collect();
+
+    let points: Vec<Value> = diff
+        .points()
+        .iter()
+        .map(|point| {
+            json!({
+                "archive": point.archive(),
+                "timestamp": point.timestamp(),
+                "first": point.first(),
+                "second": point.second(),
+            })
+        })
+        .collect();
+
+    json!({
+        "header": header,
+        "points": points,
+    })
+}
+
+/// Write each difference on its own line, header differences first.
+fn write_diff<W>(writer: &mut W, diff: &DatabaseDiff) -> io::Result<()>
+where
+    W: Write,
+{
+    for header in diff.header() {
+        match *header {
+            HeaderDiff::Aggregation(a, b) => writeln!(writer, "aggregation: {} != {}", a, b)?,
+            HeaderDiff::XFilesFactor(a, b) => writeln!(writer, "xFilesFactor: {} != {}", a, b)?,
+            HeaderDiff::Archive(i, ref a, ref b) => writeln!(
+                writer,
+                "archive {}: {} != {}",
+                i,
+                format_archive(a),
+                format_archive(b)
+            )?,
+        }
+    }
+
+    for point in diff.points() {
+        writeln!(
+            writer,
+            "archive {} at {}: {} != {}",
+            point.archive(),
+            point.timestamp(),
+            format_value(point.first()),
+            format_value(point.second())
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use memento::diff::{DatabaseDiff, HeaderDiff, PointDiff};
+    use memento::types::{AggregationType, ArchiveInfo};
+
+    use super::{diff_to_json, write_diff};
+
+    fn get_diff() -> DatabaseDiff {
+        DatabaseDiff::new(
+            vec![
+                HeaderDiff::Aggregation(AggregationType::Average, AggregationType::Sum),
+                HeaderDiff::XFilesFactor(0.5, 0.25),
+                HeaderDiff::Archive(1, Some(ArchiveInfo::new(40, 300, 2016)), None),
+            ],
+            vec![
+                PointDiff::new(0, 1500000000, Some(1.5), Some(2.0)),
+                PointDiff::new(0, 1500000060, None, Some(3.0)),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_write_diff() {
+        let mut buf = vec![];
+        write_diff(&mut buf, &get_diff()).unwrap();
+
+        assert_eq!(
+            "aggregation: average != sum\n\
+             xFilesFactor: 0.5 != 0.25\n\
+             archive 1: 5m:7d != (none)\n\
+             archive 0 at 1500000000: 1.5 != 2\n\
+             archive 0 at 1500000060: (none) != 3\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_diff_to_json() {
+        assert_eq!(
+            json!({
+                "header": [
+                    {"field": "aggregationMethod", "first": "average", "second": "sum"},
+                    {"field": "xFilesFactor", "first": 0.5, "second": 0.25},
+                    {
+                        "field": "archive",
+                        "archive": 1,
+                        "first": {
+                            "offset": 40,
+                            "secondsPerPoint": 300,
+                            "points": 2016,
+                            "retention": 604800,
+                            "size": 24192,
+                        },
+                        "second": null,
+                    },
+                ],
+                "points": [
+                    {"archive": 0, "timestamp": 1500000000, "first": 1.5, "second": 2.0},
+                    {"archive": 0, "timestamp": 1500000060, "first": null, "second": 3.0},
+                ],
+            }),
+            diff_to_json(&get_diff())
+        );
+    }
+}
@@ -0,0 +1,135 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Print the header and every point of a Whisper file
+
+use std::io::{self, Write};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde_json::Value;
+
+use memento::errors::MementoResult;
+use memento::types::MementoDatabase;
+use memento::MementoFileReader;
+
+use info::{header_to_json, write_header};
+use output::{json_arg, point_to_json, write_json};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("dump")
+        .about("Print the header and the raw points of every archive of a Whisper file")
+        .arg(Arg::with_name("FILE").required(true).help("Whisper file"))
+        .arg(json_arg())
+}
+
+pub fn run(args: &ArgMatches) -> MementoResult<i32> {
+    let path = args.value_of("FILE").unwrap();
+    let reader = MementoFileReader::new();
+    let database = reader.read_database(path)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    if args.is_present("json") {
+        write_json(&mut out, &database_to_json(&database))?;
+    } else {
+        write_database(&mut out, &database)?;
+    }
+
+    Ok(0)
+}
+
+/// Convert the header to JSON, with the raw points of each archive included
+/// in the object for the archive.
+fn database_to_json(database: &MementoDatabase) -> Value {
+    let mut value = header_to_json(database.header());
+
+    for (i, archive) in database.data().archives().iter().enumerate() {
+        let points = archive.points().iter().map(point_to_json).collect();
+        value["archives"][i]["data"] = Value::Array(points);
+    }
+
+    value
+}
+
+/// Write the header followed by the slot, timestamp, and value of every point
+/// of each archive, including points that have never been written.
+fn write_database<W>(writer: &mut W, database: &MementoDatabase) -> io::Result<()>
+where
+    W: Write,
+{
+    write_header(writer, database.header())?;
+
+    for (i, archive) in database.data().archives().iter().enumerate() {
+        writeln!(writer)?;
+        writeln!(writer, "Archive {} data", i)?;
+
+        for (slot, point) in archive.points().iter().enumerate() {
+            writeln!(writer, "{}: {}, {}", slot, point.timestamp(), point.value())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use memento::types::{
+        AggregationType, Archive, ArchiveInfo, Data, Header, MementoDatabase, Metadata, Point,
+    };
+
+    use super::{database_to_json, write_database};
+
+    fn get_database() -> MementoDatabase {
+        let metadata = Metadata::new(AggregationType::Sum, 120, 0.0, 1);
+        let header = Header::new(metadata, vec![ArchiveInfo::new(28, 60, 2)]);
+        let points = vec![Point::new(1500000000, 3.0), Point::new(0, 0.0)];
+        MementoDatabase::new(header, Data::new(vec![Archive::new(points)]))
+    }
+
+    #[test]
+    fn test_write_database() {
+        let mut buf = vec![];
+        write_database(&mut buf, &get_database()).unwrap();
+
+        assert_eq!(
+            "aggregationMethod: sum\n\
+             maxRetention: 120\n\
+             xFilesFactor: 0\n\
+             fileSize: 52\n\
+             \n\
+             Archive 0\n\
+             offset: 28\n\
+             secondsPerPoint: 60\n\
+             points: 2\n\
+             retention: 120\n\
+             size: 24\n\
+             \n\
+             Archive 0 data\n\
+             0: 1500000000, 3\n\
+             1: 0, 0\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_database_to_json() {
+        let value = database_to_json(&get_database());
+
+        assert_eq!(
+            json!([
+                {"timestamp": 1500000000, "value": 3.0},
+                {"timestamp": 0, "value": 0.0},
+            ]),
+            value["archives"][0]["data"]
+        );
+        assert_eq!(json!(120), value["maxRetention"]);
+    }
+}
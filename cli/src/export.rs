@@ -0,0 +1,135 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Export the points of a Whisper file as CSV or Graphite JSON
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use memento::errors::MementoResult;
+use memento::export::{
+    write_csv, write_database_csv, write_database_graphite_json, write_graphite_json,
+};
+use memento::{FetchRequest, MementoFileReader};
+
+use fetch::parse_time;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("export")
+        .about("Export the points of a Whisper file as CSV or Graphite JSON")
+        .arg(Arg::with_name("FILE").required(true).help("Whisper file"))
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["csv", "json"])
+                .default_value("csv")
+                .help("Output format, JSON uses the format of the Graphite render API"),
+        )
+        .arg(
+            Arg::with_name("target")
+                .long("target")
+                .takes_value(true)
+                .value_name("NAME")
+                .help("Name of the series for JSON output (default: file name without extension)"),
+        )
+        .arg(
+            Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .value_name("TIMESTAMP")
+                .help("Start of the range as a Unix timestamp (default: one day ago)"),
+        )
+        .arg(
+            Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .value_name("TIMESTAMP")
+                .help("End of the range as a Unix timestamp (default: now)"),
+        )
+        .arg(
+            Arg::with_name("all")
+                .long("all")
+                .conflicts_with_all(&["from", "until"])
+                .help("Export every archive of the file instead of a time range"),
+        )
+}
+
+/// Name of the series for a file when one isn't given: the file name
+/// without the extension, e.g. `cpu.wsp` results in `cpu`.
+fn default_target(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+pub fn run(args: &ArgMatches) -> MementoResult<i32> {
+    let path = Path::new(args.value_of("FILE").unwrap());
+    let target = args
+        .value_of("target")
+        .map(|t| t.to_owned())
+        .unwrap_or_else(|| default_target(path));
+    let json = args.value_of("format") == Some("json");
+    let reader = MementoFileReader::new();
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    if args.is_present("all") {
+        let database = reader.read_database(path)?;
+        if json {
+            write_database_graphite_json(&mut out, &target, &database)?;
+            writeln!(out)?;
+        } else {
+            write_database_csv(&mut out, &database)?;
+        }
+
+        return Ok(0);
+    }
+
+    let mut req = FetchRequest::default();
+
+    if let Some(from) = parse_time(args, "from")? {
+        req = req.with_from(from);
+    }
+
+    if let Some(until) = parse_time(args, "until")? {
+        req = req.with_until(until);
+    }
+
+    let res = reader.read(path, &req)?;
+    if json {
+        write_graphite_json(&mut out, &target, &res)?;
+        writeln!(out)?;
+    } else {
+        write_csv(&mut out, &res)?;
+    }
+
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::default_target;
+
+    #[test]
+    fn test_default_target() {
+        assert_eq!(
+            "cpu",
+            default_target(Path::new("/var/lib/graphite/cpu.wsp"))
+        );
+        assert_eq!("load.1m", default_target(Path::new("load.1m.wsp")));
+        assert_eq!("", default_target(Path::new("/")));
+    }
+}
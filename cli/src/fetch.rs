@@ -0,0 +1,160 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Print the points of a Whisper file within a time range
+
+use std::io::{self, Write};
+
+use chrono::{DateTime, TimeZone, Utc};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde_json::Value;
+
+use memento::errors::{ErrorKind, MementoError, MementoResult};
+use memento::{FetchRequest, FetchResponse, MementoFileReader};
+
+use output::{json_arg, write_json};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("fetch")
+        .about("Print the points of a Whisper file within a time range")
+        .arg(Arg::with_name("FILE").required(true).help("Whisper file"))
+        .arg(
+            Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .value_name("TIMESTAMP")
+                .help("Start of the range as a Unix timestamp (default: one day ago)"),
+        )
+        .arg(
+            Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .value_name("TIMESTAMP")
+                .help("End of the range as a Unix timestamp (default: now)"),
+        )
+        .arg(json_arg())
+}
+
+/// Parse an optional Unix timestamp argument.
+pub fn parse_time(args: &ArgMatches, name: &str) -> MementoResult<Option<DateTime<Utc>>> {
+    let val = match args.value_of(name) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    val.parse::<i64>()
+        .ok()
+        .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+        .map(Some)
+        .ok_or_else(|| {
+            MementoError::from((
+                ErrorKind::InvalidTimeRange,
+                "invalid timestamp",
+                val.to_owned(),
+            ))
+        })
+}
+
+pub fn run(args: &ArgMatches) -> MementoResult<i32> {
+    let path = args.value_of("FILE").unwrap();
+    let mut req = FetchRequest::default();
+
+    if let Some(from) = parse_time(args, "from")? {
+        req = req.with_from(from);
+    }
+
+    if let Some(until) = parse_time(args, "until")? {
+        req = req.with_until(until);
+    }
+
+    let reader = MementoFileReader::new();
+    let res = reader.read(path, &req)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    if args.is_present("json") {
+        write_json(&mut out, &response_to_json(&res))?;
+    } else {
+        write_response(&mut out, &res)?;
+    }
+
+    Ok(0)
+}
+
+/// Convert the time range and values to JSON, using the same structure as
+/// the `whisper-fetch` script (missing values are `null`).
+fn response_to_json(res: &FetchResponse) -> Value {
+    json!({
+        "start": res.from(),
+        "end": res.until(),
+        "step": res.step(),
+        "values": res.values(),
+    })
+}
+
+/// Write the timestamp and value of each interval separated by a tab, using
+/// `None` for missing values like the `whisper-fetch` script.
+fn write_response<W>(writer: &mut W, res: &FetchResponse) -> io::Result<()>
+where
+    W: Write,
+{
+    for (i, value) in res.values().iter().enumerate() {
+        let timestamp = res.from() + i as u32 * res.step();
+        match *value {
+            Some(v) => writeln!(writer, "{}\t{}", timestamp, v)?,
+            None => writeln!(writer, "{}\tNone", timestamp)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use memento::types::ArchiveInfo;
+    use memento::FetchResponse;
+
+    use super::{response_to_json, write_response};
+
+    fn get_response() -> FetchResponse {
+        let values = vec![Some(1.5), None, Some(2.0)];
+        FetchResponse::new(
+            ArchiveInfo::new(28, 60, 1440),
+            1500000000,
+            1500000180,
+            values,
+        )
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut buf = vec![];
+        write_response(&mut buf, &get_response()).unwrap();
+
+        assert_eq!(
+            "1500000000\t1.5\n1500000060\tNone\n1500000120\t2\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_response_to_json() {
+        assert_eq!(
+            json!({
+                "start": 1500000000,
+                "end": 1500000180,
+                "step": 60,
+                "values": [1.5, null, 2.0],
+            }),
+            response_to_json(&get_response())
+        );
+    }
+}
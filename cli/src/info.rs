@@ -0,0 +1,134 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Print the header of a Whisper file
+
+use std::io::{self, Write};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde_json::Value;
+
+use memento::errors::MementoResult;
+use memento::types::Header;
+use memento::MementoFileReader;
+
+use output::{archive_to_json, json_arg, metadata_to_json, write_json};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("info")
+        .about("Print the metadata and archives of a Whisper file")
+        .arg(Arg::with_name("FILE").required(true).help("Whisper file"))
+        .arg(json_arg())
+}
+
+pub fn run(args: &ArgMatches) -> MementoResult<i32> {
+    let path = args.value_of("FILE").unwrap();
+    let reader = MementoFileReader::new();
+    let header = reader.read_header(path)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    if args.is_present("json") {
+        write_json(&mut out, &header_to_json(&header))?;
+    } else {
+        write_header(&mut out, &header)?;
+    }
+
+    Ok(0)
+}
+
+/// Convert the metadata and each archive of the header to JSON.
+pub fn header_to_json(header: &Header) -> Value {
+    let mut value = metadata_to_json(header);
+    let archives = header.archive_info().iter().map(archive_to_json).collect();
+    value["archives"] = Value::Array(archives);
+    value
+}
+
+/// Write the metadata and each archive of the header in the same format as
+/// the `whisper-info` script.
+pub fn write_header<W>(writer: &mut W, header: &Header) -> io::Result<()>
+where
+    W: Write,
+{
+    let metadata = header.metadata();
+    writeln!(writer, "aggregationMethod: {}", metadata.aggregation())?;
+    writeln!(writer, "maxRetention: {}", metadata.max_retention())?;
+    writeln!(writer, "xFilesFactor: {}", metadata.x_files_factor())?;
+    writeln!(writer, "fileSize: {}", header.file_size())?;
+
+    for (i, info) in header.archive_info().iter().enumerate() {
+        writeln!(writer)?;
+        writeln!(writer, "Archive {}", i)?;
+        writeln!(writer, "offset: {}", info.offset())?;
+        writeln!(writer, "secondsPerPoint: {}", info.seconds_per_point())?;
+        writeln!(writer, "points: {}", info.num_points())?;
+        writeln!(writer, "retention: {}", info.retention())?;
+        writeln!(writer, "size: {}", info.archive_size())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use memento::types::{AggregationType, ArchiveInfo, Header, Metadata};
+
+    use super::{header_to_json, write_header};
+
+    fn get_header() -> Header {
+        let metadata = Metadata::new(AggregationType::Max, 86400, 0.5, 1);
+        Header::new(metadata, vec![ArchiveInfo::new(28, 60, 1440)])
+    }
+
+    #[test]
+    fn test_write_header() {
+        let mut buf = vec![];
+        write_header(&mut buf, &get_header()).unwrap();
+
+        assert_eq!(
+            "aggregationMethod: max\n\
+             maxRetention: 86400\n\
+             xFilesFactor: 0.5\n\
+             fileSize: 17308\n\
+             \n\
+             Archive 0\n\
+             offset: 28\n\
+             secondsPerPoint: 60\n\
+             points: 1440\n\
+             retention: 86400\n\
+             size: 17280\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_header_to_json() {
+        let value = header_to_json(&get_header());
+
+        assert_eq!(
+            json!({
+                "aggregationMethod": "max",
+                "maxRetention": 86400,
+                "xFilesFactor": 0.5,
+                "fileSize": 17308,
+                "archives": [{
+                    "offset": 28,
+                    "secondsPerPoint": 60,
+                    "points": 1440,
+                    "retention": 86400,
+                    "size": 17280,
+                }],
+            }),
+            value
+        );
+    }
+}
@@ -0,0 +1,66 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Command line interface for Memento
+
+extern crate chrono;
+#[macro_use]
+extern crate clap;
+extern crate memento;
+#[macro_use]
+extern crate serde_json;
+
+mod check;
+mod diff;
+mod dump;
+mod export;
+mod fetch;
+mod info;
+mod output;
+
+use std::process;
+
+use clap::{App, AppSettings};
+
+// Exit code used when a command fails, distinct from the exit code
+// used by commands like `diff` to indicate that differences were found.
+const EXIT_ERROR: i32 = 2;
+
+fn main() {
+    let matches = App::new("memento")
+        .version(crate_version!())
+        .about("Inspect and compare Whisper database files")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(info::subcommand())
+        .subcommand(fetch::subcommand())
+        .subcommand(dump::subcommand())
+        .subcommand(diff::subcommand())
+        .subcommand(check::subcommand())
+        .subcommand(export::subcommand())
+        .get_matches();
+
+    let res = match matches.subcommand() {
+        ("info", Some(args)) => info::run(args),
+        ("fetch", Some(args)) => fetch::run(args),
+        ("dump", Some(args)) => dump::run(args),
+        ("diff", Some(args)) => diff::run(args),
+        ("check", Some(args)) => check::run(args),
+        ("export", Some(args)) => export::run(args),
+        _ => unreachable!("unknown subcommand"),
+    };
+
+    match res {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("memento: {}", e);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
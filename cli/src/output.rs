@@ -0,0 +1,68 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Functions shared by subcommands to output Whisper files as text or JSON
+
+use std::io::{self, Write};
+
+use clap::Arg;
+use serde_json::{self, Value};
+
+use memento::types::{ArchiveInfo, Header, Point};
+
+/// Flag for subcommands to output JSON instead of text.
+pub fn json_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("json")
+        .long("json")
+        .help("Output JSON instead of text")
+}
+
+/// Write a JSON value followed by a newline.
+pub fn write_json<W>(writer: &mut W, value: &Value) -> io::Result<()>
+where
+    W: Write,
+{
+    serde_json::to_writer_pretty(&mut *writer, value)?;
+    writeln!(writer)
+}
+
+/// Convert the metadata of a header to JSON, using the same names as
+/// whisper.py does for each field.
+pub fn metadata_to_json(header: &Header) -> Value {
+    let metadata = header.metadata();
+
+    json!({
+        "aggregationMethod": metadata.aggregation().to_string(),
+        "maxRetention": metadata.max_retention(),
+        "xFilesFactor": metadata.x_files_factor(),
+        "fileSize": header.file_size(),
+    })
+}
+
+/// Convert an archive to JSON, using the same names as whisper.py does
+/// for each field.
+pub fn archive_to_json(info: &ArchiveInfo) -> Value {
+    json!({
+        "offset": info.offset(),
+        "secondsPerPoint": info.seconds_per_point(),
+        "points": info.num_points(),
+        "retention": info.retention(),
+        "size": info.archive_size(),
+    })
+}
+
+/// Convert a point to JSON. Values that can't be represented in JSON
+/// (NaN and infinity) are converted to `null`.
+pub fn point_to_json(point: &Point) -> Value {
+    json!({
+        "timestamp": point.timestamp(),
+        "value": point.value(),
+    })
+}
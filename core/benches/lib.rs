@@ -3,7 +3,9 @@ extern crate memento_core;
 extern crate test;
 
 use memento_core::encoder::{memento_encode_database, memento_encode_header};
-use memento_core::parser::{memento_parse_database, memento_parse_header};
+use memento_core::parser::{
+    memento_decode_points, memento_parse_database, memento_parse_header, memento_parse_points,
+};
 
 #[bench]
 fn bench_memento_parse_header(b: &mut test::Bencher) {
@@ -21,6 +23,30 @@ fn bench_memento_parse_database(b: &mut test::Bencher) {
     });
 }
 
+#[bench]
+fn bench_memento_parse_points(b: &mut test::Bencher) {
+    let bytes = &include_bytes!("../../tests/mean_01.wsp")[..];
+    let header = memento_parse_header(bytes).unwrap().1;
+    let info = &header.archive_info()[0];
+    let input = &bytes[info.offset() as usize..];
+
+    b.iter(|| {
+        memento_parse_points(input, info.num_points() as usize).unwrap();
+    });
+}
+
+#[bench]
+fn bench_memento_decode_points(b: &mut test::Bencher) {
+    let bytes = &include_bytes!("../../tests/mean_01.wsp")[..];
+    let header = memento_parse_header(bytes).unwrap().1;
+    let info = &header.archive_info()[0];
+    let input = &bytes[info.offset() as usize..];
+
+    b.iter(|| {
+        memento_decode_points(input, info.num_points() as usize).unwrap();
+    });
+}
+
 #[bench]
 fn bench_memento_encode_header(b: &mut test::Bencher) {
     let bytes = &include_bytes!("../../tests/mean_01.wsp")[0..76];
@@ -13,6 +13,7 @@
 use std::error;
 use std::fmt;
 use std::io;
+use std::path::{Path, PathBuf};
 
 use nom;
 
@@ -23,6 +24,39 @@ enum ErrorRepr {
     IoError(io::Error),
     ParseError(nom::IError),
     WithDescription(ErrorKind, &'static str),
+    WithDescriptionAndDetail(ErrorKind, &'static str, String),
+    WithDescriptionAndCause(ErrorKind, &'static str, Box<MementoError>),
+}
+
+/// Where in a database an error happened, when known.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ErrorContext {
+    path: Option<PathBuf>,
+    archive: Option<usize>,
+    offset: Option<u64>,
+}
+
+impl ErrorContext {
+    fn is_empty(&self) -> bool {
+        self.path.is_none() && self.archive.is_none() && self.offset.is_none()
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let mut parts = Vec::new();
+        if let Some(ref path) = self.path {
+            parts.push(format!("file {}", path.display()));
+        }
+        if let Some(archive) = self.archive {
+            parts.push(format!("archive {}", archive));
+        }
+        if let Some(offset) = self.offset {
+            parts.push(format!("offset {}", offset));
+        }
+
+        f.write_str(&parts.join(", "))
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
@@ -34,36 +68,114 @@ pub enum ErrorKind {
     InvalidTimeEnd,
     NoArchiveAvailable,
     CorruptDatabase,
+    InvalidRetention,
+    EmptyArchiveList,
+    DuplicateArchivePrecision,
+    UnsortedArchives,
+    UnevenArchivePrecision,
+    InsufficientArchiveRetention,
+    InsufficientArchivePoints,
+    LockUnavailable,
+    InvalidXFilesFactor,
 }
 
 #[derive(Debug)]
 pub struct MementoError {
     repr: ErrorRepr,
+    context: ErrorContext,
 }
 
 impl MementoError {
+    fn new(repr: ErrorRepr) -> MementoError {
+        MementoError {
+            repr: repr,
+            context: ErrorContext::default(),
+        }
+    }
+
     pub fn kind(&self) -> ErrorKind {
         match self.repr {
             ErrorRepr::IoError(_) => ErrorKind::IoError,
             ErrorRepr::ParseError(_) => ErrorKind::ParseError,
             ErrorRepr::WithDescription(kind, _) => kind,
+            ErrorRepr::WithDescriptionAndDetail(kind, _, _) => kind,
+            ErrorRepr::WithDescriptionAndCause(kind, _, _) => kind,
         }
     }
-}
 
-impl fmt::Display for MementoError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    /// Path of the database file the error happened in, if known.
+    pub fn path(&self) -> Option<&Path> {
+        self.context.path.as_deref()
+    }
+
+    /// Index of the archive the error happened in, if known.
+    pub fn archive(&self) -> Option<usize> {
+        self.context.archive
+    }
+
+    /// Byte offset in the database file of the read the error happened
+    /// in, if known.
+    pub fn offset(&self) -> Option<u64> {
+        self.context.offset
+    }
+
+    /// Set the path of the database file the error happened in, unless
+    /// it is already set.
+    pub fn with_path<P>(mut self, path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        if self.context.path.is_none() {
+            self.context.path = Some(path.as_ref().to_path_buf());
+        }
+        self
+    }
+
+    /// Set the index of the archive the error happened in, unless it is
+    /// already set.
+    pub fn with_archive(mut self, archive: usize) -> Self {
+        self.context.archive = self.context.archive.or(Some(archive));
+        self
+    }
+
+    /// Set the byte offset of the read the error happened in, unless it
+    /// is already set.
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.context.offset = self.context.offset.or(Some(offset));
+        self
+    }
+
+    /// Write the message for this error without any context.
+    fn fmt_message(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self.repr {
-            ErrorRepr::IoError(ref err) => err.fmt(f),
+            ErrorRepr::IoError(ref err) => write!(f, "{}", err),
             ErrorRepr::ParseError(ref err) => match *err {
-                nom::IError::Error(ref e) => e.fmt(f),
+                nom::IError::Error(ref e) => write!(f, "{}", e),
                 nom::IError::Incomplete(need) => write!(f, "incomplete: {:?}", need),
             },
-            ErrorRepr::WithDescription(_, desc) => desc.fmt(f),
+            ErrorRepr::WithDescription(_, desc) => f.write_str(desc),
+            ErrorRepr::WithDescriptionAndDetail(_, desc, ref detail) => {
+                write!(f, "{}: {}", desc, detail)
+            }
+            ErrorRepr::WithDescriptionAndCause(_, desc, ref cause) => {
+                write!(f, "{}: ", desc)?;
+                cause.fmt_message(f)
+            }
         }
     }
 }
 
+impl fmt::Display for MementoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        self.fmt_message(f)?;
+        if !self.context.is_empty() {
+            write!(f, " ({})", self.context)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl error::Error for MementoError {
     fn description(&self) -> &str {
         match self.repr {
@@ -73,6 +185,8 @@ impl error::Error for MementoError {
                 nom::IError::Incomplete(_) => "incomplete",
             },
             ErrorRepr::WithDescription(_, desc) => desc,
+            ErrorRepr::WithDescriptionAndDetail(_, desc, _) => desc,
+            ErrorRepr::WithDescriptionAndCause(_, desc, _) => desc,
         }
     }
 
@@ -83,6 +197,19 @@ impl error::Error for MementoError {
                 nom::IError::Error(ref e) => Some(e),
                 _ => None,
             },
+            ErrorRepr::WithDescriptionAndCause(_, _, ref cause) => Some(cause.as_ref()),
+            _ => None,
+        }
+    }
+
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match self.repr {
+            ErrorRepr::IoError(ref err) => Some(err),
+            ErrorRepr::ParseError(ref err) => match *err {
+                nom::IError::Error(ref e) => Some(e),
+                _ => None,
+            },
+            ErrorRepr::WithDescriptionAndCause(_, _, ref cause) => Some(cause.as_ref()),
             _ => None,
         }
     }
@@ -90,24 +217,100 @@ impl error::Error for MementoError {
 
 impl From<io::Error> for MementoError {
     fn from(err: io::Error) -> MementoError {
-        MementoError {
-            repr: ErrorRepr::IoError(err),
-        }
+        MementoError::new(ErrorRepr::IoError(err))
     }
 }
 
 impl From<nom::IError> for MementoError {
     fn from(err: nom::IError) -> MementoError {
-        MementoError {
-            repr: ErrorRepr::ParseError(err),
-        }
+        MementoError::new(ErrorRepr::ParseError(err))
     }
 }
 
 impl From<(ErrorKind, &'static str)> for MementoError {
     fn from((kind, msg): (ErrorKind, &'static str)) -> MementoError {
+        MementoError::new(ErrorRepr::WithDescription(kind, msg))
+    }
+}
+
+impl From<(ErrorKind, &'static str, String)> for MementoError {
+    fn from((kind, msg, detail): (ErrorKind, &'static str, String)) -> MementoError {
+        MementoError::new(ErrorRepr::WithDescriptionAndDetail(kind, msg, detail))
+    }
+}
+
+impl From<(ErrorKind, &'static str, MementoError)> for MementoError {
+    /// Wrap an error with a new kind and description, keeping the original
+    /// as the cause. Any context from the original error is kept.
+    fn from((kind, msg, cause): (ErrorKind, &'static str, MementoError)) -> MementoError {
+        let context = cause.context.clone();
         MementoError {
-            repr: ErrorRepr::WithDescription(kind, msg),
+            repr: ErrorRepr::WithDescriptionAndCause(kind, msg, Box::new(cause)),
+            context: context,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io;
+    use std::path::Path;
+
+    use super::{ErrorKind, MementoError};
+
+    #[test]
+    fn test_memento_error_display_context() {
+        let err = MementoError::from((ErrorKind::CorruptDatabase, "corrupt database"));
+        assert_eq!("corrupt database", err.to_string());
+
+        let err = err
+            .with_path("/tmp/foo.wsp")
+            .with_archive(1)
+            .with_offset(100);
+        assert_eq!(
+            "corrupt database (file /tmp/foo.wsp, archive 1, offset 100)",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_memento_error_context_not_replaced() {
+        let err = MementoError::from((ErrorKind::ParseError, "parse error"))
+            .with_offset(100)
+            .with_archive(1)
+            .with_offset(28)
+            .with_archive(0)
+            .with_path("/tmp/foo.wsp")
+            .with_path("/tmp/bar.wsp");
+
+        assert_eq!(Some(Path::new("/tmp/foo.wsp")), err.path());
+        assert_eq!(Some(1), err.archive());
+        assert_eq!(Some(100), err.offset());
+    }
+
+    #[test]
+    fn test_memento_error_with_cause() {
+        let cause = MementoError::from(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "short read. wanted 12, read 4",
+        ))
+        .with_offset(40);
+        let err = MementoError::from((
+            ErrorKind::CorruptDatabase,
+            "I/O error reading archive",
+            cause,
+        ))
+        .with_archive(0);
+
+        assert_eq!(ErrorKind::CorruptDatabase, err.kind());
+        assert_eq!(Some(40), err.offset());
+        assert_eq!(
+            "I/O error reading archive: short read. wanted 12, read 4 (archive 0, offset 40)",
+            err.to_string()
+        );
+
+        let source = err.source().unwrap();
+        assert!(source.source().unwrap().is::<io::Error>());
+    }
+}
@@ -15,4 +15,6 @@ extern crate nom;
 pub mod encoder;
 pub mod errors;
 pub mod parser;
+pub mod retention;
 pub mod types;
+pub mod view;
@@ -10,11 +10,13 @@
 
 //! Functions to parse Whisper files from a stream of bytes
 
-use nom::{be_f32, be_f64, be_u32, IResult};
+use byteorder::{ByteOrder, NetworkEndian};
+use nom::{be_f32, be_f64, be_u32, IResult, Needed};
 
 use types::{
     AggregationType, Archive, ArchiveInfo, Data, Header, MementoDatabase, Metadata, Point,
 };
+use view::ArchiveView;
 
 named!(pub memento_parse_aggregation_type<&[u8], AggregationType>,
        switch!(be_u32,
@@ -103,18 +105,56 @@ named!(pub memento_parse_header<&[u8], Header>,
        )
 );
 
+pub fn memento_parse_points(input: &[u8], num: usize) -> IResult<&[u8], Vec<Point>> {
+    count!(input, memento_parse_point, num)
+}
+
+/// Decode a single point from the first 12 bytes of `input`, which must be
+/// long enough to contain one.
+#[inline]
+pub(crate) fn decode_point(input: &[u8]) -> Point {
+    Point::new(
+        NetworkEndian::read_u32(&input[0..4]),
+        NetworkEndian::read_f64(&input[4..12]),
+    )
+}
+
+/// Decode `num` points in bulk, producing the same points as
+/// `memento_parse_points` without going through a parser for each one.
+pub fn memento_decode_points(input: &[u8], num: usize) -> IResult<&[u8], Vec<Point>> {
+    let size = Point::storage() as usize;
+    let len = num * size;
+
+    if input.len() < len {
+        return IResult::Incomplete(Needed::Size(len));
+    }
+
+    let (bytes, remaining) = input.split_at(len);
+    let points = bytes.chunks_exact(size).map(decode_point).collect();
+    IResult::Done(remaining, points)
+}
+
 pub fn memento_parse_archive<'a, 'b>(
     input: &'a [u8],
     info: &'b ArchiveInfo,
 ) -> IResult<&'a [u8], Archive> {
     let (remaining, points) = try_parse!(
         input,
-        count!(memento_parse_point, info.num_points() as usize)
+        apply!(memento_decode_points, info.num_points() as usize)
     );
 
     IResult::Done(remaining, Archive::new(points))
 }
 
+/// Get a view of the points of an archive without decoding them.
+pub fn memento_parse_archive_view<'a, 'b>(
+    input: &'a [u8],
+    info: &'b ArchiveInfo,
+) -> IResult<&'a [u8], ArchiveView<'a>> {
+    let (remaining, bytes) = try_parse!(input, take!(info.archive_size()));
+    IResult::Done(remaining, ArchiveView::new(bytes))
+}
+
 named!(pub memento_parse_database<&[u8], MementoDatabase>,
        do_parse!(
            header: memento_parse_header                              >>
@@ -130,9 +170,10 @@ mod tests {
     };
 
     use super::{
-        memento_parse_aggregation_type, memento_parse_archive, memento_parse_archive_info,
-        memento_parse_archive_infos, memento_parse_data, memento_parse_database,
-        memento_parse_header, memento_parse_metadata, memento_parse_point,
+        memento_decode_points, memento_parse_aggregation_type, memento_parse_archive,
+        memento_parse_archive_info, memento_parse_archive_infos, memento_parse_archive_view,
+        memento_parse_data, memento_parse_database, memento_parse_header, memento_parse_metadata,
+        memento_parse_point, memento_parse_points,
     };
 
     #[test]
@@ -234,6 +275,49 @@ mod tests {
         assert_eq!(expected, res);
     }
 
+    #[test]
+    fn test_memento_parse_points() {
+        // Python:
+        // struct.pack('>Ld', 1511396041, 42.0).hex()
+        // struct.pack('>Ld', 1511396051, 42.0).hex()
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        let bytes = vec![
+            0x5a, 0x16, 0x12, 0xc9,
+            0x40, 0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+
+            0x5a, 0x16, 0x12, 0xd3,
+            0x40, 0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+        ];
+
+        let (remaining, res) = memento_parse_points(&bytes, 1).unwrap();
+        assert_eq!(vec![Point::new(1511396041, 42.0)], res);
+        assert_eq!(12, remaining.len());
+
+        let res = memento_parse_points(&bytes, 2).unwrap().1;
+        assert_eq!(
+            vec![Point::new(1511396041, 42.0), Point::new(1511396051, 42.0)],
+            res
+        );
+
+        assert!(memento_parse_points(&bytes, 3).is_incomplete());
+    }
+
+    #[test]
+    fn test_memento_decode_points() {
+        let bytes = &include_bytes!("../../tests/mean_01.wsp")[..];
+        let header = memento_parse_header(bytes).unwrap().1;
+        let info = &header.archive_info()[0];
+        let input = &bytes[info.offset() as usize..];
+        let num = info.num_points() as usize;
+
+        let (remaining1, expected) = memento_parse_points(input, num).unwrap();
+        let (remaining2, res) = memento_decode_points(input, num).unwrap();
+
+        assert_eq!(expected, res);
+        assert_eq!(remaining1, remaining2);
+        assert!(memento_decode_points(&input[..11], 1).is_incomplete());
+    }
+
     #[test]
     fn test_memento_parse_archive() {
         let point1 = Point::new(1511396041, 42.0);
@@ -258,6 +342,32 @@ mod tests {
         assert_eq!(expected, res);
     }
 
+    #[test]
+    fn test_memento_parse_archive_view() {
+        let point1 = Point::new(1511396041, 42.0);
+        let point2 = Point::new(1511396051, 42.0);
+        let expected = Archive::new(vec![point1, point2]);
+
+        let info = ArchiveInfo::new(28, 10, 2);
+
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        let bytes = vec![
+            0x5a, 0x16, 0x12, 0xc9,
+            0x40, 0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+
+            0x5a, 0x16, 0x12, 0xd3,
+            0x40, 0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+
+            0xff
+        ];
+
+        let (remaining, view) = memento_parse_archive_view(&bytes, &info).unwrap();
+        assert_eq!(1, remaining.len());
+        assert_eq!(expected, view.to_archive());
+
+        assert!(memento_parse_archive_view(&bytes[..20], &info).is_incomplete());
+    }
+
     #[test]
     fn test_memento_parse_header() {
         let metadata = Metadata::new(AggregationType::Min, 86400, 0.5, 1);
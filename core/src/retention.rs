@@ -0,0 +1,270 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Functions to parse and format Graphite retention definitions
+//!
+//! A retention definition describes the precision and retention of a single
+//! archive as `precision:retention`, for example `10s:1d` or `60:1440`. The
+//! precision is a number of seconds or a number followed by a unit. The
+//! retention is a number of points or a number followed by a unit. Multiple
+//! definitions are separated by commas, for example `10s:1d,1m:7d,1h:5y`.
+
+use errors::{ErrorKind, MementoError, MementoResult};
+use types::ArchiveInfo;
+
+// Units supported for precision and retention and the number of seconds in
+// each. A unit may be given as any prefix of its name, the first matching name
+// wins (so `m` means minutes, not months which aren't supported anyway).
+const UNITS: &[(&str, u32)] = &[
+    ("seconds", 1),
+    ("minutes", 60),
+    ("hours", 3600),
+    ("days", 86400),
+    ("weeks", 86400 * 7),
+    ("years", 86400 * 365),
+];
+
+// Units used when formatting precision and retention, largest first. Weeks
+// are skipped so that common definitions like `1m:7d` are kept as written.
+const FORMAT_UNITS: &[(&str, u32)] = &[
+    ("y", 86400 * 365),
+    ("d", 86400),
+    ("h", 3600),
+    ("m", 60),
+    ("s", 1),
+];
+
+fn invalid(desc: &'static str, val: &str) -> MementoError {
+    MementoError::from((ErrorKind::InvalidRetention, desc, val.to_owned()))
+}
+
+/// Parse a bare number or a number of seconds from a number followed by a
+/// unit (e.g. `30` or `30s` or `5min`), along with whether a unit was given.
+fn parse_quantity(val: &str) -> Option<(u32, bool)> {
+    let split = val.find(|c: char| !c.is_ascii_digit()).unwrap_or(val.len());
+    let (digits, unit) = val.split_at(split);
+    let num = digits.parse::<u32>().ok()?;
+
+    if unit.is_empty() {
+        return Some((num, false));
+    }
+
+    if !unit.chars().all(|c| c.is_ascii_lowercase()) {
+        return None;
+    }
+
+    UNITS
+        .iter()
+        .find(|&&(name, _)| name.starts_with(unit))
+        .and_then(|&(_, secs)| num.checked_mul(secs))
+        .map(|secs| (secs, true))
+}
+
+/// Parse a single retention definition (e.g. `10s:1d`) into an archive with
+/// the corresponding seconds per point and number of points. The offset of
+/// the returned archive is always zero.
+///
+/// # Errors
+///
+/// Return an error if the definition is malformed, uses an unknown unit,
+/// results in a precision or number of points of zero, or results in a
+/// retention too long to be stored in a database.
+pub fn parse_retention(def: &str) -> MementoResult<ArchiveInfo> {
+    let def = def.trim();
+    let mut parts = def.splitn(2, ':');
+    let (precision, points) = match (parts.next(), parts.next()) {
+        (Some(p), Some(r)) => (p, r),
+        _ => return Err(invalid("invalid retention definition", def)),
+    };
+
+    let seconds_per_point = match parse_quantity(precision) {
+        Some((secs, _)) if secs > 0 => secs,
+        _ => return Err(invalid("invalid precision specification", precision)),
+    };
+
+    let num_points = match parse_quantity(points) {
+        Some((num, false)) => num,
+        Some((secs, true)) => secs / seconds_per_point,
+        None => return Err(invalid("invalid retention specification", points)),
+    };
+
+    if num_points == 0 {
+        return Err(invalid("retention shorter than precision", def));
+    }
+
+    if seconds_per_point.checked_mul(num_points).is_none() {
+        return Err(invalid("retention too long", def));
+    }
+
+    Ok(ArchiveInfo::new(0, seconds_per_point, num_points))
+}
+
+/// Parse a comma separated list of retention definitions (e.g.
+/// `10s:1d,1m:7d,1h:5y`) into archives, in the order given. The offset of
+/// each returned archive is always zero.
+///
+/// # Errors
+///
+/// Return an error if the list is empty or any definition is invalid.
+pub fn parse_retentions(defs: &str) -> MementoResult<Vec<ArchiveInfo>> {
+    if defs.trim().is_empty() {
+        return Err(invalid("empty retention definition", defs));
+    }
+
+    defs.split(',').map(parse_retention).collect()
+}
+
+/// Format a number of seconds using the largest unit that evenly divides it.
+fn format_seconds(secs: u64) -> String {
+    let &(unit, size) = FORMAT_UNITS
+        .iter()
+        .find(|&&(_, size)| secs.is_multiple_of(u64::from(size)))
+        .unwrap_or(&("s", 1));
+
+    format!("{}{}", secs / u64::from(size), unit)
+}
+
+/// Format an archive as a retention definition (e.g. `1m:7d`) using the
+/// largest units that evenly divide the precision and retention.
+pub fn format_retention(info: &ArchiveInfo) -> String {
+    format!(
+        "{}:{}",
+        format_seconds(u64::from(info.seconds_per_point())),
+        format_seconds(info.retention())
+    )
+}
+
+/// Format archives as a comma separated list of retention definitions
+/// (e.g. `10s:1d,1m:7d,1h:5y`), in the order given.
+pub fn format_retentions(infos: &[ArchiveInfo]) -> String {
+    infos
+        .iter()
+        .map(format_retention)
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use errors::ErrorKind;
+    use types::ArchiveInfo;
+
+    use super::{format_retention, format_retentions, parse_retention, parse_retentions};
+
+    #[test]
+    fn test_parse_retention_units() {
+        assert_eq!(
+            ArchiveInfo::new(0, 10, 8640),
+            parse_retention("10s:1d").unwrap()
+        );
+        assert_eq!(
+            ArchiveInfo::new(0, 60, 10080),
+            parse_retention("1min:1w").unwrap()
+        );
+        assert_eq!(
+            ArchiveInfo::new(0, 3600, 43800),
+            parse_retention("1h:5y").unwrap()
+        );
+        assert_eq!(
+            ArchiveInfo::new(0, 300, 288),
+            parse_retention(" 5m:1days ").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_retention_bare_numbers() {
+        assert_eq!(
+            ArchiveInfo::new(0, 60, 1440),
+            parse_retention("60:1440").unwrap()
+        );
+        assert_eq!(
+            ArchiveInfo::new(0, 60, 1440),
+            parse_retention("60:1d").unwrap()
+        );
+        assert_eq!(
+            ArchiveInfo::new(0, 60, 1440),
+            parse_retention("1m:1440").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_retention_invalid() {
+        let bad = vec![
+            "",
+            "10s",
+            "10s:",
+            ":1d",
+            "10x:1d",
+            "10s:1x",
+            "s:1d",
+            "10S:1d",
+            "0s:1d",
+            "1d:1h",
+            "10s:1d:1y",
+            "-10s:1d",
+            "60:100000000",
+            "1s:200y",
+        ];
+
+        for def in bad {
+            let err = parse_retention(def).unwrap_err();
+            assert_eq!(ErrorKind::InvalidRetention, err.kind(), "{}", def);
+        }
+    }
+
+    #[test]
+    fn test_parse_retentions() {
+        let infos = parse_retentions("10s:1d,1m:7d,1h:5y").unwrap();
+
+        assert_eq!(
+            vec![
+                ArchiveInfo::new(0, 10, 8640),
+                ArchiveInfo::new(0, 60, 10080),
+                ArchiveInfo::new(0, 3600, 43800),
+            ],
+            infos
+        );
+    }
+
+    #[test]
+    fn test_parse_retentions_invalid() {
+        assert_eq!(
+            ErrorKind::InvalidRetention,
+            parse_retentions("").unwrap_err().kind()
+        );
+        assert_eq!(
+            ErrorKind::InvalidRetention,
+            parse_retentions("10s:1d,").unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_format_retention() {
+        assert_eq!("10s:1d", format_retention(&ArchiveInfo::new(0, 10, 8640)));
+        assert_eq!("1m:7d", format_retention(&ArchiveInfo::new(76, 60, 10080)));
+        assert_eq!("1h:5y", format_retention(&ArchiveInfo::new(0, 3600, 43800)));
+        assert_eq!(
+            "10s:12340s",
+            format_retention(&ArchiveInfo::new(0, 10, 1234))
+        );
+    }
+
+    #[test]
+    fn test_format_retentions_round_trip() {
+        let defs = "10s:1d,1m:7d,5m:30d,1h:5y";
+        let infos = parse_retentions(defs).unwrap();
+
+        assert_eq!(defs, format_retentions(&infos));
+        assert_eq!(
+            "1m:1d",
+            format_retentions(&parse_retentions("60:1440").unwrap())
+        );
+    }
+}
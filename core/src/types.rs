@@ -10,6 +10,11 @@
 
 //! Structures that define the Whisper file format on disk
 
+use std::fmt;
+use std::str::FromStr;
+
+use errors::{ErrorKind, MementoError, MementoResult};
+
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct MementoDatabase {
     header: Header,
@@ -72,6 +77,212 @@ impl Header {
             .iter()
             .fold(self.size(), |acc, info| acc + info.archive_size())
     }
+
+    /// Make sure the archives of this header are a valid configuration for
+    /// a database, using the same rules as whisper.py.
+    ///
+    /// There must be at least one archive and archives must be sorted from
+    /// highest to lowest precision (fewest seconds per point first). Each
+    /// archive must have a retention that fits in the maximum retention of
+    /// the metadata (a `u32`) and must:
+    ///
+    /// * Have a precision different from all other archives.
+    /// * Have a precision that evenly divides the precision of the next archive.
+    /// * Cover a shorter time period than the next archive.
+    /// * Have enough points to consolidate into a single point of the next archive.
+    ///
+    /// # Errors
+    ///
+    /// Return an error with a kind specific to the first rule that was violated.
+    pub fn validate(&self) -> MementoResult<()> {
+        let archives = self.archive_info();
+
+        if archives.is_empty() {
+            return Err(MementoError::from((
+                ErrorKind::EmptyArchiveList,
+                "at least one archive is required",
+            )));
+        }
+
+        for (i, archive) in archives.iter().enumerate() {
+            if archive.seconds_per_point() == 0 || archive.num_points() == 0 {
+                return Err(MementoError::from((
+                    ErrorKind::InvalidRetention,
+                    "archives must have a non-zero precision and number of points",
+                    format!(
+                        "archive{} ({}:{})",
+                        i,
+                        archive.seconds_per_point(),
+                        archive.num_points()
+                    ),
+                )));
+            }
+
+            if archive.retention() > u64::from(u32::MAX) {
+                return Err(MementoError::from((
+                    ErrorKind::InvalidRetention,
+                    "archive retention is too long to be stored",
+                    format!(
+                        "archive{} ({}:{})",
+                        i,
+                        archive.seconds_per_point(),
+                        archive.num_points()
+                    ),
+                )));
+            }
+        }
+
+        for (i, pair) in archives.windows(2).enumerate() {
+            let (archive, next) = (&pair[0], &pair[1]);
+            let detail = || {
+                format!(
+                    "archive{} ({}:{}), archive{} ({}:{})",
+                    i,
+                    archive.seconds_per_point(),
+                    archive.num_points(),
+                    i + 1,
+                    next.seconds_per_point(),
+                    next.num_points()
+                )
+            };
+
+            if archive.seconds_per_point() == next.seconds_per_point() {
+                return Err(MementoError::from((
+                    ErrorKind::DuplicateArchivePrecision,
+                    "archives may not have the same precision",
+                    detail(),
+                )));
+            }
+
+            if archive.seconds_per_point() > next.seconds_per_point() {
+                return Err(MementoError::from((
+                    ErrorKind::UnsortedArchives,
+                    "archives must be sorted from highest to lowest precision",
+                    detail(),
+                )));
+            }
+
+            if next.seconds_per_point() % archive.seconds_per_point() != 0 {
+                return Err(MementoError::from((
+                    ErrorKind::UnevenArchivePrecision,
+                    "higher precision archives' precision must evenly divide all lower \
+                     precision archives' precision",
+                    detail(),
+                )));
+            }
+
+            if next.retention() <= archive.retention() {
+                return Err(MementoError::from((
+                    ErrorKind::InsufficientArchiveRetention,
+                    "lower precision archives must cover larger time intervals than \
+                     higher precision archives",
+                    detail(),
+                )));
+            }
+
+            let points_per_consolidation = next.seconds_per_point() / archive.seconds_per_point();
+            if archive.num_points() < points_per_consolidation {
+                return Err(MementoError::from((
+                    ErrorKind::InsufficientArchivePoints,
+                    "each archive must have at least enough points to consolidate to the \
+                     next archive",
+                    detail(),
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Builder for a `Header` describing a new database.
+///
+/// Only the seconds per point and number of points of each archive are
+/// used. Archives are sorted from highest to lowest precision and offsets,
+/// the archive count, and the maximum retention are computed from them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderBuilder {
+    aggregation: AggregationType,
+    x_files_factor: f32,
+    archives: Vec<ArchiveInfo>,
+}
+
+impl HeaderBuilder {
+    /// Create a new builder with no archives that uses averages for aggregation
+    /// and an x-files-factor of 0.5, the same defaults as whisper.py.
+    pub fn new() -> HeaderBuilder {
+        HeaderBuilder {
+            aggregation: AggregationType::default(),
+            x_files_factor: 0.5,
+            archives: Vec::new(),
+        }
+    }
+
+    /// Use the given aggregation method for the database.
+    pub fn with_aggregation(mut self, val: AggregationType) -> Self {
+        self.aggregation = val;
+        self
+    }
+
+    /// Use the given x-files-factor for the database.
+    pub fn with_x_files_factor(mut self, val: f32) -> Self {
+        self.x_files_factor = val;
+        self
+    }
+
+    /// Use the given archives for the database, in any order.
+    pub fn with_archives(mut self, val: Vec<ArchiveInfo>) -> Self {
+        self.archives = val;
+        self
+    }
+
+    /// Create a new header from the values of this builder.
+    pub fn build(&self) -> Header {
+        let mut archives = self.archives.clone();
+        archives.sort_by_key(|a| a.seconds_per_point());
+
+        let count = archives.len();
+        let mut offset = Metadata::storage() + ArchiveInfo::storage() * count as u64;
+        let mut infos = Vec::with_capacity(count);
+
+        for info in archives {
+            infos.push(ArchiveInfo::new(
+                offset as u32,
+                info.seconds_per_point(),
+                info.num_points(),
+            ));
+            offset += info.archive_size();
+        }
+
+        // Retention too long to be stored is rejected by `Header::validate`.
+        let max_retention = infos.iter().map(|i| i.retention()).max().unwrap_or(0);
+        let metadata = Metadata::new(
+            self.aggregation,
+            max_retention.min(u64::from(u32::MAX)) as u32,
+            self.x_files_factor,
+            count as u32,
+        );
+
+        Header::new(metadata, infos)
+    }
+}
+
+impl Default for HeaderBuilder {
+    fn default() -> HeaderBuilder {
+        HeaderBuilder::new()
+    }
+}
+
+impl<'a> From<&'a Header> for HeaderBuilder {
+    /// Create a new builder with the aggregation method, x-files-factor,
+    /// and archives of an existing header.
+    fn from(header: &'a Header) -> HeaderBuilder {
+        let metadata = header.metadata();
+        HeaderBuilder::new()
+            .with_aggregation(metadata.aggregation())
+            .with_x_files_factor(metadata.x_files_factor())
+            .with_archives(header.archive_info().to_vec())
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -87,12 +298,130 @@ pub enum AggregationType {
     AbsMin = 8,
 }
 
+impl AggregationType {
+    /// Every aggregation method, in the order of their numeric codes.
+    pub const ALL: [AggregationType; 8] = [
+        AggregationType::Average,
+        AggregationType::Sum,
+        AggregationType::Last,
+        AggregationType::Max,
+        AggregationType::Min,
+        AggregationType::AvgZero,
+        AggregationType::AbsMax,
+        AggregationType::AbsMin,
+    ];
+
+    /// Get the name whisper.py uses for the aggregation method.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AggregationType::Average => "average",
+            AggregationType::Sum => "sum",
+            AggregationType::Last => "last",
+            AggregationType::Max => "max",
+            AggregationType::Min => "min",
+            AggregationType::AvgZero => "avg_zero",
+            AggregationType::AbsMax => "absmax",
+            AggregationType::AbsMin => "absmin",
+        }
+    }
+
+    /// Aggregate a series of known values in the same way as whisper.py.
+    ///
+    /// Return `None` if there are no values. Since every value given is
+    /// known, `AvgZero` is the same as `Average` here. Use `aggregate_series`
+    /// for series that may contain unknown values.
+    pub fn aggregate(&self, known: &[f64]) -> Option<f64> {
+        let first = *known.first()?;
+        let sum = || known.iter().fold(0.0, |acc, v| acc + v);
+
+        // Ties keep the first value to match the `max` and `min` builtins
+        // used by whisper.py.
+        let value = match *self {
+            AggregationType::Average | AggregationType::AvgZero => sum() / known.len() as f64,
+            AggregationType::Sum => sum(),
+            AggregationType::Last => known[known.len() - 1],
+            AggregationType::Max => known
+                .iter()
+                .fold(first, |acc, &v| if v > acc { v } else { acc }),
+            AggregationType::Min => known
+                .iter()
+                .fold(first, |acc, &v| if v < acc { v } else { acc }),
+            AggregationType::AbsMax => {
+                known
+                    .iter()
+                    .fold(first, |acc, &v| if v.abs() > acc.abs() { v } else { acc })
+            }
+            AggregationType::AbsMin => {
+                known
+                    .iter()
+                    .fold(first, |acc, &v| if v.abs() < acc.abs() { v } else { acc })
+            }
+        };
+
+        Some(value)
+    }
+
+    /// Aggregate a series of values where `None` is used for unknown values,
+    /// in the same way as whisper.py does when propagating to lower precision
+    /// archives.
+    ///
+    /// Return `None` if there are no known values or if the ratio of known
+    /// values to the length of the series is less than `x_files_factor`.
+    /// Unknown values are ignored except by `AvgZero`, which treats them as
+    /// zero.
+    pub fn aggregate_series(&self, series: &[Option<f64>], x_files_factor: f32) -> Option<f64> {
+        let known: Vec<f64> = series.iter().filter_map(|v| *v).collect();
+        if known.is_empty() {
+            return None;
+        }
+
+        let known_percent = known.len() as f64 / series.len() as f64;
+        if known_percent < f64::from(x_files_factor) {
+            return None;
+        }
+
+        match *self {
+            AggregationType::AvgZero => {
+                let total = series.iter().fold(0.0, |acc, v| acc + v.unwrap_or(0.0));
+                Some(total / series.len() as f64)
+            }
+            _ => self.aggregate(&known),
+        }
+    }
+}
+
 impl Default for AggregationType {
     fn default() -> AggregationType {
         AggregationType::Average
     }
 }
 
+impl fmt::Display for AggregationType {
+    /// Format the aggregation method using the name whisper.py uses for it.
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        self.name().fmt(f)
+    }
+}
+
+impl FromStr for AggregationType {
+    type Err = MementoError;
+
+    /// Parse an aggregation method from the name whisper.py uses for it.
+    fn from_str(s: &str) -> MementoResult<AggregationType> {
+        AggregationType::ALL
+            .iter()
+            .find(|method| method.name() == s)
+            .cloned()
+            .ok_or_else(|| {
+                MementoError::from((
+                    ErrorKind::ParseError,
+                    "unknown aggregation method",
+                    s.to_owned(),
+                ))
+            })
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct Metadata {
     aggregation: AggregationType,
@@ -189,8 +518,8 @@ impl ArchiveInfo {
     }
 
     #[inline]
-    pub fn retention(&self) -> u32 {
-        self.num_points * self.seconds_per_point
+    pub fn retention(&self) -> u64 {
+        u64::from(self.num_points) * u64::from(self.seconds_per_point)
     }
 }
 
@@ -258,7 +587,103 @@ impl Point {
 
 #[cfg(test)]
 mod tests {
-    use super::{AggregationType, ArchiveInfo, Header, Metadata};
+    use errors::ErrorKind;
+
+    use super::{AggregationType, ArchiveInfo, Header, HeaderBuilder, Metadata};
+
+    fn header_for_archives(archives: Vec<(u32, u32)>) -> Header {
+        let infos = archives
+            .into_iter()
+            .map(|(secs, points)| ArchiveInfo::new(0, secs, points))
+            .collect();
+
+        Header::new(Metadata::default(), infos)
+    }
+
+    fn validate_kind(archives: Vec<(u32, u32)>) -> ErrorKind {
+        header_for_archives(archives).validate().unwrap_err().kind()
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let known = vec![3.0, -5.0, 1.0];
+
+        assert_eq!(Some(-1.0 / 3.0), AggregationType::Average.aggregate(&known));
+        assert_eq!(Some(-1.0), AggregationType::Sum.aggregate(&known));
+        assert_eq!(Some(1.0), AggregationType::Last.aggregate(&known));
+        assert_eq!(Some(3.0), AggregationType::Max.aggregate(&known));
+        assert_eq!(Some(-5.0), AggregationType::Min.aggregate(&known));
+        assert_eq!(Some(-1.0 / 3.0), AggregationType::AvgZero.aggregate(&known));
+        assert_eq!(Some(-5.0), AggregationType::AbsMax.aggregate(&known));
+        assert_eq!(Some(1.0), AggregationType::AbsMin.aggregate(&known));
+    }
+
+    #[test]
+    fn test_aggregate_empty() {
+        assert_eq!(None, AggregationType::Average.aggregate(&[]));
+        assert_eq!(None, AggregationType::Sum.aggregate(&[]));
+        assert_eq!(None, AggregationType::AvgZero.aggregate(&[]));
+    }
+
+    #[test]
+    fn test_aggregate_ties() {
+        // Python: max([-0.0, 0.0]), min([0.0, -0.0]), max([2.0, -2.0], key=abs)
+        let max = AggregationType::Max.aggregate(&[-0.0, 0.0]).unwrap();
+        let min = AggregationType::Min.aggregate(&[0.0, -0.0]).unwrap();
+        let abs_max = AggregationType::AbsMax.aggregate(&[2.0, -2.0]).unwrap();
+        let abs_min = AggregationType::AbsMin.aggregate(&[-2.0, 2.0]).unwrap();
+
+        assert!(max.is_sign_negative());
+        assert!(min.is_sign_positive());
+        assert_eq!(2.0, abs_max);
+        assert_eq!(-2.0, abs_min);
+    }
+
+    #[test]
+    fn test_aggregate_series() {
+        let series = vec![Some(3.0), None, Some(-5.0), Some(1.0)];
+
+        assert_eq!(
+            Some(-1.0 / 3.0),
+            AggregationType::Average.aggregate_series(&series, 0.5)
+        );
+        assert_eq!(
+            Some(-0.25),
+            AggregationType::AvgZero.aggregate_series(&series, 0.5)
+        );
+        assert_eq!(
+            Some(-5.0),
+            AggregationType::AbsMax.aggregate_series(&series, 0.75)
+        );
+    }
+
+    #[test]
+    fn test_aggregate_series_x_files_factor() {
+        let series = vec![Some(3.0), None, None, Some(1.0)];
+
+        assert_eq!(
+            Some(4.0),
+            AggregationType::Sum.aggregate_series(&series, 0.5)
+        );
+        assert_eq!(None, AggregationType::Sum.aggregate_series(&series, 0.51));
+        assert_eq!(
+            None,
+            AggregationType::Sum.aggregate_series(&[None, None], 0.0)
+        );
+        assert_eq!(None, AggregationType::Sum.aggregate_series(&[], 0.0));
+    }
+
+    #[test]
+    fn test_aggregation_from_str() {
+        for method in AggregationType::ALL.iter() {
+            assert_eq!(*method, method.to_string().parse().unwrap());
+        }
+
+        assert_eq!(AggregationType::AvgZero, "avg_zero".parse().unwrap());
+
+        let res = "median".parse::<AggregationType>();
+        assert_eq!(ErrorKind::ParseError, res.unwrap_err().kind());
+    }
 
     #[test]
     fn test_header_size() {
@@ -274,4 +699,115 @@ mod tests {
         assert_eq!(76, header.size());
         assert_eq!(744556, header.file_size());
     }
+
+    #[test]
+    fn test_header_builder() {
+        let header = HeaderBuilder::new()
+            .with_aggregation(AggregationType::Sum)
+            .with_x_files_factor(0.1)
+            .with_archives(vec![
+                ArchiveInfo::new(0, 300, 8640),
+                ArchiveInfo::new(0, 10, 8640),
+                ArchiveInfo::new(0, 60, 10080),
+            ])
+            .build();
+
+        let metadata = Metadata::new(AggregationType::Sum, 2592000, 0.1, 3);
+        let info1 = ArchiveInfo::new(52, 10, 8640);
+        let info2 = ArchiveInfo::new(103732, 60, 10080);
+        let info3 = ArchiveInfo::new(224692, 300, 8640);
+
+        assert_eq!(Header::new(metadata, vec![info1, info2, info3]), header);
+        assert_eq!(328372, header.file_size());
+    }
+
+    #[test]
+    fn test_header_builder_long_retention() {
+        let header = HeaderBuilder::new()
+            .with_archives(vec![ArchiveInfo::new(0, 65536, 65536)])
+            .build();
+
+        assert_eq!(1 << 32, header.archive_info()[0].retention());
+        assert_eq!(u32::MAX, header.metadata().max_retention());
+    }
+
+    #[test]
+    fn test_header_builder_from_header() {
+        let metadata = Metadata::new(AggregationType::Max, 86400, 0.5, 1);
+        let header = Header::new(metadata, vec![ArchiveInfo::new(28, 10, 8640)]);
+
+        assert_eq!(header, HeaderBuilder::from(&header).build());
+    }
+
+    #[test]
+    fn test_header_validate_success() {
+        let archives = vec![(10, 8640), (60, 10080), (300, 8640), (3600, 8760)];
+        assert!(header_for_archives(archives).validate().is_ok());
+        assert!(header_for_archives(vec![(60, 1)]).validate().is_ok());
+    }
+
+    #[test]
+    fn test_header_validate_empty() {
+        assert_eq!(ErrorKind::EmptyArchiveList, validate_kind(vec![]));
+    }
+
+    #[test]
+    fn test_header_validate_zero_precision_or_points() {
+        assert_eq!(ErrorKind::InvalidRetention, validate_kind(vec![(0, 100)]));
+        assert_eq!(
+            ErrorKind::InvalidRetention,
+            validate_kind(vec![(10, 100), (60, 0)])
+        );
+    }
+
+    #[test]
+    fn test_header_validate_retention_too_long() {
+        assert_eq!(
+            ErrorKind::InvalidRetention,
+            validate_kind(vec![(65536, 65536)])
+        );
+        assert!(header_for_archives(vec![(65535, 65537)]).validate().is_ok());
+    }
+
+    #[test]
+    fn test_header_validate_duplicate_precision() {
+        let kind = validate_kind(vec![(10, 8640), (10, 17280)]);
+        assert_eq!(ErrorKind::DuplicateArchivePrecision, kind);
+    }
+
+    #[test]
+    fn test_header_validate_unsorted() {
+        let kind = validate_kind(vec![(60, 10080), (10, 8640)]);
+        assert_eq!(ErrorKind::UnsortedArchives, kind);
+    }
+
+    #[test]
+    fn test_header_validate_uneven_precision() {
+        let kind = validate_kind(vec![(10, 8640), (25, 8640)]);
+        assert_eq!(ErrorKind::UnevenArchivePrecision, kind);
+    }
+
+    #[test]
+    fn test_header_validate_insufficient_retention() {
+        let kind = validate_kind(vec![(10, 8640), (60, 1440)]);
+        assert_eq!(ErrorKind::InsufficientArchiveRetention, kind);
+    }
+
+    #[test]
+    fn test_header_validate_insufficient_points() {
+        let kind = validate_kind(vec![(10, 5), (60, 1440)]);
+        assert_eq!(ErrorKind::InsufficientArchivePoints, kind);
+    }
+
+    #[test]
+    fn test_header_validate_error_detail() {
+        let err = header_for_archives(vec![(10, 8640), (10, 17280)])
+            .validate()
+            .unwrap_err();
+
+        assert_eq!(
+            "archives may not have the same precision: archive0 (10:8640), archive1 (10:17280)",
+            err.to_string()
+        );
+    }
 }
@@ -0,0 +1,185 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Borrowed views over encoded Whisper data that decode values on demand
+
+use std::iter::FusedIterator;
+
+use parser::decode_point;
+use types::{Archive, Point};
+
+const POINT_SIZE: usize = 12;
+
+/// View of the encoded points of an archive that decodes each `Point` as
+/// it is accessed instead of allocating all of them up front.
+///
+/// Any trailing bytes that don't make up a complete point are ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArchiveView<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ArchiveView<'a> {
+    pub fn new(bytes: &'a [u8]) -> ArchiveView<'a> {
+        ArchiveView { bytes: bytes }
+    }
+
+    /// Number of points in the archive.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len() / POINT_SIZE
+    }
+
+    /// Return true if the archive has no points.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decode the point at the given index, if there is one.
+    pub fn get(&self, index: usize) -> Option<Point> {
+        if index < self.len() {
+            Some(decode_point(&self.bytes[index * POINT_SIZE..]))
+        } else {
+            None
+        }
+    }
+
+    /// Iterate over the points of the archive in storage order.
+    pub fn iter(&self) -> PointIter<'a> {
+        PointIter {
+            bytes: &self.bytes[..self.len() * POINT_SIZE],
+        }
+    }
+
+    /// Decode all points, copying them into an owned `Archive`.
+    pub fn to_archive(&self) -> Archive {
+        Archive::new(self.iter().collect())
+    }
+}
+
+impl<'a> IntoIterator for ArchiveView<'a> {
+    type Item = Point;
+    type IntoIter = PointIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &ArchiveView<'a> {
+    type Item = Point;
+    type IntoIter = PointIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator that decodes each point of an `ArchiveView`.
+#[derive(Debug, Clone)]
+pub struct PointIter<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for PointIter<'a> {
+    type Item = Point;
+
+    fn next(&mut self) -> Option<Point> {
+        if self.bytes.len() < POINT_SIZE {
+            return None;
+        }
+
+        let point = decode_point(self.bytes);
+        self.bytes = &self.bytes[POINT_SIZE..];
+        Some(point)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.bytes.len() / POINT_SIZE;
+        (len, Some(len))
+    }
+}
+
+impl<'a> DoubleEndedIterator for PointIter<'a> {
+    fn next_back(&mut self) -> Option<Point> {
+        if self.bytes.len() < POINT_SIZE {
+            return None;
+        }
+
+        let start = self.bytes.len() - POINT_SIZE;
+        let point = decode_point(&self.bytes[start..]);
+        self.bytes = &self.bytes[..start];
+        Some(point)
+    }
+}
+
+impl<'a> ExactSizeIterator for PointIter<'a> {}
+
+impl<'a> FusedIterator for PointIter<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use encoder::memento_encode_archive;
+    use types::{Archive, Point};
+
+    use super::ArchiveView;
+
+    fn get_encoded_archive() -> (Archive, Vec<u8>) {
+        let archive = Archive::new(vec![
+            Point::new(1500000000, 1.0),
+            Point::new(1500000060, 2.5),
+            Point::new(0, 0.0),
+        ]);
+
+        let mut buf = vec![];
+        memento_encode_archive(&mut buf, &archive).unwrap();
+        (archive, buf)
+    }
+
+    #[test]
+    fn test_archive_view_get() {
+        let (archive, buf) = get_encoded_archive();
+        let view = ArchiveView::new(&buf);
+
+        assert_eq!(3, view.len());
+        assert_eq!(Some(archive.points()[1].clone()), view.get(1));
+        assert_eq!(None, view.get(3));
+    }
+
+    #[test]
+    fn test_archive_view_iter() {
+        let (archive, buf) = get_encoded_archive();
+        let view = ArchiveView::new(&buf);
+
+        assert_eq!(3, view.iter().len());
+        assert_eq!(archive.points(), &view.iter().collect::<Vec<_>>()[..]);
+        assert_eq!(Some(Point::new(0, 0.0)), view.iter().next_back());
+        assert_eq!(archive, view.to_archive());
+    }
+
+    #[test]
+    fn test_archive_view_trailing_bytes() {
+        let (archive, buf) = get_encoded_archive();
+        let view = ArchiveView::new(&buf[..buf.len() - 1]);
+
+        assert_eq!(2, view.len());
+        assert_eq!(&archive.points()[..2], &view.iter().collect::<Vec<_>>()[..]);
+        assert_eq!(2, view.iter().rev().count());
+    }
+
+    #[test]
+    fn test_archive_view_empty() {
+        let view = ArchiveView::new(&[]);
+
+        assert!(view.is_empty());
+        assert_eq!(None, view.iter().next());
+    }
+}
@@ -34,7 +34,8 @@ def _read_header(header):
     info = {
         'aggregationMethod': aggregation,
         'maxRetention': header.metadata.max_retention,
-        'xFilesFactor': header.metadata.x_files_factor
+        'xFilesFactor': header.metadata.x_files_factor,
+        'retention': ffi.string(header.retention).decode('utf-8')
     }
 
     archives = []
@@ -0,0 +1,504 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Listener for the Graphite (carbon) plaintext protocol that writes the
+//! points it receives to Whisper files
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use memento_core::errors::{ErrorKind, MementoError, MementoResult};
+use memento_core::types::{Header, Point};
+
+use lock::LockMode;
+use write::MementoFileWriter;
+
+// Largest UDP datagram that can be received, the maximum payload of UDP.
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+// Number of lines received over a TCP connection after which points are
+// written even if the client is still sending more.
+const MAX_BATCH_LINES: usize = 10000;
+
+// Longest line accepted over a TCP connection, including the newline. The
+// same as the default of the Twisted line receiver used by carbon.
+const MAX_LINE_LENGTH: usize = 16384;
+
+// Number of TCP connections handled at once by default, each on its own
+// thread. Connections past this are closed straight away.
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// Parse a line of the plaintext protocol (`metric.path value timestamp`)
+/// into the name of the metric and the point to write for it.
+///
+/// Timestamps may have a fractional part, which is discarded.
+///
+/// # Errors
+///
+/// Return an error if the line doesn't have exactly three fields, if the
+/// value isn't a finite number (NaN and infinity are rejected), or if the
+/// timestamp isn't a positive Unix timestamp.
+pub fn parse_line(line: &str) -> MementoResult<(String, Point)> {
+    let invalid = || {
+        MementoError::from((
+            ErrorKind::ParseError,
+            "invalid plaintext protocol line",
+            line.to_owned(),
+        ))
+    };
+
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 3 {
+        return Err(invalid());
+    }
+
+    let value = fields[1]
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(invalid)?;
+    let timestamp = fields[2]
+        .parse::<f64>()
+        .ok()
+        .filter(|&ts| ts >= 1.0 && ts <= f64::from(u32::MAX))
+        .ok_or_else(invalid)?;
+
+    Ok((fields[0].to_owned(), Point::new(timestamp as u32, value)))
+}
+
+/// Get the path of the Whisper file for a metric under `root`, with each
+/// dot separated part of the name as a directory, e.g. `foo.bar.baz` results
+/// in `root/foo/bar/baz.wsp`.
+///
+/// # Errors
+///
+/// Return an error if any part of the name is empty or contains a path
+/// separator, so that names can't refer to files outside of `root`.
+pub fn metric_path(root: &Path, name: &str) -> MementoResult<PathBuf> {
+    let mut path = root.to_path_buf();
+
+    for part in name.split('.') {
+        if part.is_empty() || part.contains(&['/', '\\', '\0'][..]) {
+            return Err(MementoError::from((
+                ErrorKind::ParseError,
+                "invalid metric name",
+                name.to_owned(),
+            )));
+        }
+
+        path.push(part);
+    }
+
+    path.set_extension("wsp");
+    Ok(path)
+}
+
+/// Write points for metrics to Whisper files under a root directory,
+/// creating files that don't exist yet with the same archives.
+///
+/// Files are locked while being written so that multiple threads (or other
+/// processes that lock files) can safely write to the same file.
+#[derive(Debug)]
+pub struct MetricStore {
+    root: PathBuf,
+    header: Header,
+    writer: MementoFileWriter,
+    create: Mutex<()>,
+}
+
+impl MetricStore {
+    /// Create a new store for files under `root` that creates missing files
+    /// with the archives, aggregation method, and x-files-factor of `header`.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if the archives are not a valid configuration for a
+    /// database (see `Header::validate`).
+    pub fn new<P>(root: P, header: &Header) -> MementoResult<Self>
+    where
+        P: AsRef<Path>,
+    {
+        header.validate()?;
+
+        Ok(MetricStore {
+            root: root.as_ref().to_path_buf(),
+            header: header.clone(),
+            writer: MementoFileWriter::new().with_lock(LockMode::Blocking),
+            create: Mutex::new(()),
+        })
+    }
+
+    /// Directory that files are written under.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Header used to create missing files.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Write points for a metric to its file (see `metric_path`), creating
+    /// the file and any missing directories first if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if the name of the metric is invalid, if the file
+    /// could not be created, or if the points could not be written (see
+    /// `MementoFileWriter::update_many`). Errors include the path of the file.
+    pub fn write(&self, name: &str, points: &[Point]) -> MementoResult<()> {
+        let path = metric_path(&self.root, name)?;
+        self.create_missing(&path)
+            .and_then(|_| self.writer.update_many(&path, points))
+            .map_err(|e| e.with_path(&path))
+    }
+
+    /// Create the file at `path` if it doesn't exist yet. Files are only
+    /// created by one thread at a time so that no thread tries to write to a
+    /// file that another thread hasn't finished creating.
+    fn create_missing(&self, path: &Path) -> MementoResult<()> {
+        if path.exists() {
+            return Ok(());
+        }
+
+        let _guard = self.create.lock().unwrap_or_else(|e| e.into_inner());
+        if path.exists() {
+            return Ok(());
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        self.writer.create(path, &self.header)?;
+        Ok(())
+    }
+}
+
+/// Parse each line and write the points of each metric with a single update,
+/// passing invalid lines and errors writing files to `on_error`.
+fn write_lines<'a, I>(store: &MetricStore, lines: I, on_error: &ErrorHandler)
+where
+    I: Iterator<Item = &'a str>,
+{
+    let mut metrics: HashMap<String, Vec<Point>> = HashMap::new();
+
+    for line in lines.map(str::trim).filter(|l| !l.is_empty()) {
+        match parse_line(line) {
+            Ok((name, point)) => metrics.entry(name).or_default().push(point),
+            Err(e) => on_error(e),
+        }
+    }
+
+    for (name, points) in metrics {
+        if let Err(e) = store.write(&name, &points) {
+            on_error(e);
+        }
+    }
+}
+
+/// Read lines from a TCP connection until it is closed, writing the points
+/// received so far whenever there is no more data immediately available.
+///
+/// A line longer than `MAX_LINE_LENGTH` is reported as an error and the
+/// connection is closed, so that a client that never sends a newline can't
+/// make the server buffer data without bound.
+fn handle_tcp(store: &MetricStore, stream: TcpStream, on_error: &ErrorHandler) {
+    let mut reader = BufReader::new(stream);
+    let mut batch = Vec::new();
+    let mut lines = 0;
+
+    loop {
+        let start = batch.len();
+        match reader
+            .by_ref()
+            .take(MAX_LINE_LENGTH as u64)
+            .read_until(b'\n', &mut batch)
+        {
+            Ok(0) => break,
+            Ok(len) if len == MAX_LINE_LENGTH && batch.last() != Some(&b'\n') => {
+                batch.truncate(start);
+                on_error(MementoError::from((
+                    ErrorKind::ParseError,
+                    "plaintext protocol line too long",
+                    format!("more than {} bytes", MAX_LINE_LENGTH),
+                )));
+                break;
+            }
+            Ok(_) => lines += 1,
+            Err(e) => {
+                on_error(MementoError::from(e));
+                break;
+            }
+        }
+
+        if reader.buffer().is_empty() || lines >= MAX_BATCH_LINES {
+            write_lines(store, String::from_utf8_lossy(&batch).lines(), on_error);
+            batch.clear();
+            lines = 0;
+        }
+    }
+
+    write_lines(store, String::from_utf8_lossy(&batch).lines(), on_error);
+}
+
+/// Receive datagrams forever, writing the points of each one.
+fn handle_udp(store: &MetricStore, socket: &UdpSocket, on_error: &ErrorHandler) {
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) => write_lines(
+                store,
+                String::from_utf8_lossy(&buf[..len]).lines(),
+                on_error,
+            ),
+            Err(e) => on_error(MementoError::from(e)),
+        }
+    }
+}
+
+type ErrorHandler = Fn(MementoError) + Send + Sync;
+
+/// Count of the TCP connections being handled, decremented when the thread
+/// handling a connection ends (or could not be started).
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Server for the Graphite plaintext protocol over TCP and UDP that writes
+/// the points it receives to a `MetricStore`.
+///
+/// Each line is a metric name, value, and Unix timestamp separated by
+/// whitespace. Points received together (in the same datagram or without a
+/// pause on a TCP connection) are written to each file with a single update.
+pub struct CarbonServer {
+    store: Arc<MetricStore>,
+    tcp: Option<TcpListener>,
+    udp: Option<UdpSocket>,
+    on_error: Arc<ErrorHandler>,
+    max_connections: usize,
+}
+
+impl CarbonServer {
+    /// Create a new server that writes to the given store and isn't
+    /// listening on any address yet. Errors are ignored by default.
+    pub fn new(store: MetricStore) -> Self {
+        CarbonServer {
+            store: Arc::new(store),
+            tcp: None,
+            udp: None,
+            on_error: Arc::new(|_| ()),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+
+    /// Handle at most `max` TCP connections at once (1024 by default).
+    /// Connections accepted while this many are open are closed without
+    /// reading from them and reported to the error handler.
+    pub fn with_max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// Call `handler` with invalid lines and errors writing files or
+    /// receiving data, e.g. to log them. It may be called from any thread.
+    pub fn with_error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(MementoError) + Send + Sync + 'static,
+    {
+        self.on_error = Arc::new(handler);
+        self
+    }
+
+    /// Listen for TCP connections on the given address, returning the
+    /// address that was bound (e.g. to find the port when using port 0).
+    ///
+    /// # Errors
+    ///
+    /// Return an error if the address could not be bound.
+    pub fn listen_tcp<A>(&mut self, addr: A) -> MementoResult<SocketAddr>
+    where
+        A: ToSocketAddrs,
+    {
+        let listener = TcpListener::bind(addr)?;
+        let local = listener.local_addr()?;
+        self.tcp = Some(listener);
+        Ok(local)
+    }
+
+    /// Listen for UDP datagrams on the given address, returning the address
+    /// that was bound (e.g. to find the port when using port 0).
+    ///
+    /// # Errors
+    ///
+    /// Return an error if the address could not be bound.
+    pub fn listen_udp<A>(&mut self, addr: A) -> MementoResult<SocketAddr>
+    where
+        A: ToSocketAddrs,
+    {
+        let socket = UdpSocket::bind(addr)?;
+        let local = socket.local_addr()?;
+        self.udp = Some(socket);
+        Ok(local)
+    }
+
+    /// Accept connections and datagrams forever, handling UDP and each TCP
+    /// connection on a separate thread, up to the maximum number of
+    /// connections (see `with_max_connections`).
+    ///
+    /// # Errors
+    ///
+    /// Return an error if the server isn't listening on any address or a
+    /// thread could not be started. Errors handling individual connections
+    /// and lines are passed to the error handler instead.
+    pub fn run(self) -> MementoResult<()> {
+        if self.tcp.is_none() && self.udp.is_none() {
+            return Err(MementoError::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not listening on any address",
+            )));
+        }
+
+        let udp = match self.udp {
+            Some(socket) => {
+                let store = Arc::clone(&self.store);
+                let on_error = Arc::clone(&self.on_error);
+                Some(
+                    thread::Builder::new()
+                        .name("carbon-udp".to_owned())
+                        .spawn(move || handle_udp(&store, &socket, &*on_error))?,
+                )
+            }
+            None => None,
+        };
+
+        let listener = match self.tcp {
+            Some(listener) => listener,
+            None => {
+                // Receiving datagrams never stops
+                let _ = udp.map(|t| t.join());
+                return Ok(());
+            }
+        };
+
+        let active = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    (self.on_error)(MementoError::from(e));
+                    continue;
+                }
+            };
+
+            // Only the accepting thread increments the count so it can't
+            // go past the maximum between checking and incrementing it.
+            if active.load(Ordering::SeqCst) >= self.max_connections {
+                let peer = stream
+                    .peer_addr()
+                    .map(|addr| addr.to_string())
+                    .unwrap_or_else(|_| "unknown address".to_owned());
+                (self.on_error)(MementoError::from(io::Error::other(format!(
+                    "too many connections, closing connection from {}",
+                    peer
+                ))));
+                continue;
+            }
+
+            active.fetch_add(1, Ordering::SeqCst);
+            let guard = ConnectionGuard(Arc::clone(&active));
+            let store = Arc::clone(&self.store);
+            let on_error = Arc::clone(&self.on_error);
+            thread::Builder::new()
+                .name("carbon-tcp".to_owned())
+                .spawn(move || {
+                    let _guard = guard;
+                    handle_tcp(&store, stream, &*on_error)
+                })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use memento_core::errors::ErrorKind;
+    use memento_core::types::Point;
+
+    use super::{metric_path, parse_line};
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            ("foo.bar".to_owned(), Point::new(1500000000, 1.5)),
+            parse_line("foo.bar 1.5 1500000000").unwrap()
+        );
+        assert_eq!(
+            ("foo.bar".to_owned(), Point::new(1500000000, -2.0)),
+            parse_line("  foo.bar\t-2  1500000000.75 ").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_line_invalid() {
+        for line in &[
+            "foo.bar 1.5",
+            "foo.bar 1.5 1500000000 extra",
+            "foo.bar one 1500000000",
+            "foo.bar nan 1500000000",
+            "foo.bar NaN 1500000000",
+            "foo.bar inf 1500000000",
+            "foo.bar -infinity 1500000000",
+            "foo.bar 1.5 -1",
+            "foo.bar 1.5 99999999999",
+        ] {
+            let err = parse_line(line).unwrap_err();
+            assert_eq!(ErrorKind::ParseError, err.kind());
+        }
+    }
+
+    #[test]
+    fn test_metric_path() {
+        let root = Path::new("/var/lib/whisper");
+
+        assert_eq!(
+            Path::new("/var/lib/whisper/foo/bar/baz.wsp"),
+            metric_path(root, "foo.bar.baz").unwrap()
+        );
+        assert_eq!(
+            Path::new("/var/lib/whisper/load.wsp"),
+            metric_path(root, "load").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_metric_path_invalid() {
+        let root = Path::new("/var/lib/whisper");
+
+        for name in &["", "foo..bar", ".foo", "foo.", "foo/bar", "../etc/passwd"] {
+            let err = metric_path(root, name).unwrap_err();
+            assert_eq!(ErrorKind::ParseError, err.kind());
+        }
+    }
+}
@@ -0,0 +1,575 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Functions to check the integrity of Whisper databases
+
+use std::fmt;
+
+use byteorder::{ByteOrder, NetworkEndian};
+use chrono::Utc;
+
+use memento_core::errors::ErrorKind;
+use memento_core::parser::{memento_parse_aggregation_type, memento_parse_archive_infos};
+use memento_core::types::{AggregationType, ArchiveInfo, Header, Metadata};
+use memento_core::view::ArchiveView;
+
+use repair::is_base_marker;
+
+/// A problem found with a database.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The file is too short to contain the metadata or list of archives.
+    TruncatedHeader { expected: u64, actual: u64 },
+    /// The aggregation method code isn't one that is supported.
+    UnknownAggregation(u32),
+    /// The x-files-factor isn't between 0 and 1.
+    InvalidXFilesFactor(f32),
+    /// The archives aren't a valid configuration for a database (see
+    /// `Header::validate`).
+    InvalidArchives(ErrorKind, String),
+    /// The maximum retention isn't the retention of the lowest precision
+    /// archive.
+    MaxRetention { expected: u64, actual: u32 },
+    /// The archive at the given index doesn't start immediately after the
+    /// header or previous archive.
+    ArchiveOffset {
+        archive: usize,
+        expected: u64,
+        actual: u64,
+    },
+    /// The size of the file isn't the size required for the header and
+    /// all of the archives.
+    FileSize { expected: u64, actual: u64 },
+    /// A point with a timestamp that isn't a multiple of the precision of
+    /// its archive.
+    UnalignedPoint {
+        archive: usize,
+        index: usize,
+        timestamp: u32,
+    },
+    /// A point with a timestamp in the future.
+    FuturePoint {
+        archive: usize,
+        index: usize,
+        timestamp: u32,
+    },
+    /// A point with a timestamp further before the newest point of the
+    /// database than the maximum retention.
+    ExpiredPoint {
+        archive: usize,
+        index: usize,
+        timestamp: u32,
+    },
+    /// A point with a value that is not a number.
+    NanValue {
+        archive: usize,
+        index: usize,
+        timestamp: u32,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Problem::TruncatedHeader { expected, actual } => write!(
+                f,
+                "truncated header: expected at least {} bytes, found {}",
+                expected, actual
+            ),
+            Problem::UnknownAggregation(code) => write!(f, "unknown aggregation method: {}", code),
+            Problem::InvalidXFilesFactor(xff) => write!(f, "invalid x-files-factor: {}", xff),
+            Problem::InvalidArchives(_, ref msg) => write!(f, "invalid archives: {}", msg),
+            Problem::MaxRetention { expected, actual } => {
+                write!(f, "max retention: expected {}, found {}", expected, actual)
+            }
+            Problem::ArchiveOffset {
+                archive,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "archive {} offset: expected {}, found {}",
+                archive, expected, actual
+            ),
+            Problem::FileSize { expected, actual } => write!(
+                f,
+                "file size: expected {} bytes, found {}",
+                expected, actual
+            ),
+            Problem::UnalignedPoint {
+                archive,
+                index,
+                timestamp,
+            } => write!(
+                f,
+                "archive {} point {}: unaligned timestamp {}",
+                archive, index, timestamp
+            ),
+            Problem::FuturePoint {
+                archive,
+                index,
+                timestamp,
+            } => write!(
+                f,
+                "archive {} point {}: future timestamp {}",
+                archive, index, timestamp
+            ),
+            Problem::ExpiredPoint {
+                archive,
+                index,
+                timestamp,
+            } => write!(
+                f,
+                "archive {} point {}: timestamp {} beyond max retention",
+                archive, index, timestamp
+            ),
+            Problem::NanValue {
+                archive,
+                index,
+                timestamp,
+            } => write!(
+                f,
+                "archive {} point {}: NaN value at {}",
+                archive, index, timestamp
+            ),
+        }
+    }
+}
+
+/// All problems found with a database.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CheckReport {
+    problems: Vec<Problem>,
+}
+
+impl CheckReport {
+    pub fn new(problems: Vec<Problem>) -> Self {
+        CheckReport { problems: problems }
+    }
+
+    /// Problems found, header problems first and then point problems
+    /// ordered by archive and then by position in the archive.
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    /// Return true if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Parse the header of a database, recording problems with the metadata
+/// along the way. Return `None` if there aren't enough bytes for it.
+fn check_header(bytes: &[u8], problems: &mut Vec<Problem>) -> Option<Header> {
+    let actual = bytes.len() as u64;
+    if actual < Metadata::storage() {
+        problems.push(Problem::TruncatedHeader {
+            expected: Metadata::storage(),
+            actual: actual,
+        });
+        return None;
+    }
+
+    // The aggregation method is parsed separately so that an unknown one
+    // doesn't prevent the rest of the file from being checked.
+    let code = NetworkEndian::read_u32(&bytes[0..4]);
+    let aggregation = memento_parse_aggregation_type(&bytes[0..4])
+        .to_full_result()
+        .unwrap_or_else(|_| {
+            problems.push(Problem::UnknownAggregation(code));
+            AggregationType::default()
+        });
+
+    let metadata = Metadata::new(
+        aggregation,
+        NetworkEndian::read_u32(&bytes[4..8]),
+        NetworkEndian::read_f32(&bytes[8..12]),
+        NetworkEndian::read_u32(&bytes[12..16]),
+    );
+
+    if !(0.0..=1.0).contains(&metadata.x_files_factor()) {
+        problems.push(Problem::InvalidXFilesFactor(metadata.x_files_factor()));
+    }
+
+    let expected = Metadata::storage() + metadata.archive_info_size();
+    if actual < expected {
+        problems.push(Problem::TruncatedHeader {
+            expected: expected,
+            actual: actual,
+        });
+        return None;
+    }
+
+    let start = Metadata::storage() as usize;
+    memento_parse_archive_infos(&bytes[start..], &metadata)
+        .to_full_result()
+        .ok()
+        .map(|infos| Header::new(metadata, infos))
+}
+
+/// Check that archives start immediately after the header and each other
+/// and that the file is exactly large enough to hold all of them.
+fn check_layout(header: &Header, len: u64, problems: &mut Vec<Problem>) {
+    let mut expected = header.size();
+
+    for (i, info) in header.archive_info().iter().enumerate() {
+        let actual = u64::from(info.offset());
+        if actual != expected {
+            problems.push(Problem::ArchiveOffset {
+                archive: i,
+                expected: expected,
+                actual: actual,
+            });
+        }
+
+        expected = actual + info.archive_size();
+    }
+
+    if header.file_size() != len {
+        problems.push(Problem::FileSize {
+            expected: header.file_size(),
+            actual: len,
+        });
+    }
+}
+
+/// Check each point of an archive that has been written. Points at or
+/// before `oldest` are beyond the maximum retention of the database. A base
+/// marker written by `repair::repair` isn't a point and isn't checked.
+fn check_points(
+    archive: usize,
+    info: &ArchiveInfo,
+    view: &ArchiveView,
+    now: u32,
+    oldest: u32,
+    problems: &mut Vec<Problem>,
+) {
+    for (index, point) in view.iter().enumerate() {
+        let timestamp = point.timestamp();
+        if timestamp == 0 || is_base_marker(info, index, timestamp) {
+            continue;
+        }
+
+        if !timestamp.is_multiple_of(info.seconds_per_point()) {
+            problems.push(Problem::UnalignedPoint {
+                archive: archive,
+                index: index,
+                timestamp: timestamp,
+            });
+        }
+
+        if timestamp > now {
+            problems.push(Problem::FuturePoint {
+                archive: archive,
+                index: index,
+                timestamp: timestamp,
+            });
+        }
+
+        if timestamp <= oldest {
+            problems.push(Problem::ExpiredPoint {
+                archive: archive,
+                index: index,
+                timestamp: timestamp,
+            });
+        }
+
+        if point.value().is_nan() {
+            problems.push(Problem::NanValue {
+                archive: archive,
+                index: index,
+                timestamp: timestamp,
+            });
+        }
+    }
+}
+
+/// Get the contents of each archive that are actually in the file, any
+/// part of an archive past the end of the file has already been reported.
+fn archive_views<'a>(header: &Header, bytes: &'a [u8]) -> Vec<ArchiveView<'a>> {
+    header
+        .archive_info()
+        .iter()
+        .map(|info| {
+            let start = (info.offset() as usize).min(bytes.len());
+            let end =
+                (u64::from(info.offset()) + info.archive_size()).min(bytes.len() as u64) as usize;
+            ArchiveView::new(&bytes[start..end.max(start)])
+        })
+        .collect()
+}
+
+/// Check the contents of a database as of `now`, see `check`.
+fn check_at(bytes: &[u8], now: u32) -> CheckReport {
+    let mut problems = Vec::new();
+    let header = match check_header(bytes, &mut problems) {
+        Some(header) => header,
+        None => return CheckReport::new(problems),
+    };
+
+    if let Err(e) = header.validate() {
+        problems.push(Problem::InvalidArchives(e.kind(), e.to_string()));
+    }
+
+    let expected = header
+        .archive_info()
+        .iter()
+        .map(|info| info.retention())
+        .max()
+        .unwrap_or(0);
+    if u64::from(header.metadata().max_retention()) != expected {
+        problems.push(Problem::MaxRetention {
+            expected: expected,
+            actual: header.metadata().max_retention(),
+        });
+    }
+
+    check_layout(&header, bytes.len() as u64, &mut problems);
+
+    // Retention is measured back from the newest point rather than from the
+    // current time so that databases that simply haven't been written to in
+    // a while aren't reported. Points in the future are reported separately.
+    let views = archive_views(&header, bytes);
+    let newest = views
+        .iter()
+        .flat_map(|view| view.iter())
+        .map(|point| point.timestamp())
+        .filter(|&timestamp| timestamp <= now)
+        .max()
+        .unwrap_or(0);
+    let oldest = u64::from(newest).saturating_sub(expected) as u32;
+
+    for (i, (info, view)) in header.archive_info().iter().zip(&views).enumerate() {
+        if info.seconds_per_point() == 0 {
+            continue;
+        }
+
+        check_points(i, info, view, now, oldest, &mut problems);
+    }
+
+    CheckReport::new(problems)
+}
+
+/// Check the raw contents of a database for problems without trusting any
+/// part of it.
+///
+/// The header is checked for an unknown aggregation method, an x-files-factor
+/// outside of 0 to 1, an invalid archive configuration, a maximum retention
+/// that doesn't match the archives, archives that aren't laid out one after
+/// the other, and a file size that doesn't match the header. Every point that
+/// has been written is checked for a timestamp that isn't aligned to the
+/// precision of its archive, a timestamp in the future (based on the current
+/// time), a timestamp further before the newest point of the database than
+/// the maximum retention, and a NaN value.
+pub fn check(bytes: &[u8]) -> CheckReport {
+    check_at(bytes, Utc::now().timestamp() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64;
+
+    use memento_core::encoder::{memento_encode_archive, memento_encode_header};
+    use memento_core::errors::ErrorKind;
+    use memento_core::types::{
+        AggregationType, Archive, ArchiveInfo, Header, HeaderBuilder, Metadata, Point,
+    };
+
+    use super::{check_at, Problem};
+
+    fn get_database(points: Vec<Point>) -> Vec<u8> {
+        let header = HeaderBuilder::new()
+            .with_aggregation(AggregationType::Max)
+            .with_archives(vec![
+                ArchiveInfo::new(0, 60, 5),
+                ArchiveInfo::new(0, 300, 2),
+            ])
+            .build();
+
+        let mut buf = vec![];
+        memento_encode_header(&mut buf, &header).unwrap();
+        memento_encode_archive(&mut buf, &Archive::new(points)).unwrap();
+        memento_encode_archive(&mut buf, &Archive::new(vec![Point::default(); 2])).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_check_valid() {
+        let now = 1500000000;
+        let bytes = get_database(vec![
+            Point::new(now - 60, 1.0),
+            Point::new(now, 2.0),
+            Point::default(),
+            Point::default(),
+            Point::default(),
+        ]);
+
+        assert!(check_at(&bytes, now).is_ok());
+
+        // A base marker written when repairing isn't a point
+        let bytes = get_database(vec![
+            Point::new(now - 119, 0.0),
+            Point::new(now, 2.0),
+            Point::default(),
+            Point::default(),
+            Point::default(),
+        ]);
+        assert!(check_at(&bytes, now).is_ok());
+    }
+
+    #[test]
+    fn test_check_points() {
+        let now = 1500000000;
+        let bytes = get_database(vec![
+            Point::new(now - 58, 1.0),
+            Point::new(now + 60, 2.0),
+            Point::new(now - 120, f64::NAN),
+            Point::default(),
+            Point::default(),
+        ]);
+
+        assert_eq!(
+            &[
+                Problem::UnalignedPoint {
+                    archive: 0,
+                    index: 0,
+                    timestamp: now - 58,
+                },
+                Problem::FuturePoint {
+                    archive: 0,
+                    index: 1,
+                    timestamp: now + 60,
+                },
+                Problem::NanValue {
+                    archive: 0,
+                    index: 2,
+                    timestamp: now - 120,
+                },
+            ],
+            check_at(&bytes, now).problems()
+        );
+    }
+
+    #[test]
+    fn test_check_expired_points() {
+        let now = 1500000000;
+        let bytes = get_database(vec![
+            Point::new(now - 60, 1.0),
+            Point::new(now, 2.0),
+            Point::new(now - 600, 3.0),
+            Point::new(now - 540, 4.0),
+            Point::default(),
+        ]);
+        let expected = [Problem::ExpiredPoint {
+            archive: 0,
+            index: 2,
+            timestamp: now - 600,
+        }];
+
+        assert_eq!(&expected, check_at(&bytes, now).problems());
+        // Databases that haven't been written to recently are fine
+        assert_eq!(&expected, check_at(&bytes, now + 86400).problems());
+    }
+
+    #[test]
+    fn test_check_truncated_file() {
+        let bytes = get_database(vec![Point::default(); 5]);
+        let report = check_at(&bytes[..bytes.len() - 12], 1500000000);
+
+        assert_eq!(
+            &[Problem::FileSize {
+                expected: bytes.len() as u64,
+                actual: bytes.len() as u64 - 12,
+            }],
+            report.problems()
+        );
+
+        let report = check_at(&bytes[..20], 1500000000);
+        assert_eq!(
+            &[Problem::TruncatedHeader {
+                expected: 40,
+                actual: 20,
+            }],
+            report.problems()
+        );
+    }
+
+    #[test]
+    fn test_check_header() {
+        let mut bytes = get_database(vec![Point::default(); 5]);
+        // Aggregation method
+        bytes[3] = 42;
+        // Max retention
+        bytes[7] = 0;
+        // Offset of the second archive
+        bytes[31] += 12;
+
+        let problems = check_at(&bytes, 1500000000).problems().to_vec();
+
+        assert_eq!(Problem::UnknownAggregation(42), problems[0]);
+        assert_eq!(
+            Problem::MaxRetention {
+                expected: 600,
+                actual: 512,
+            },
+            problems[1]
+        );
+        assert_eq!(
+            Problem::ArchiveOffset {
+                archive: 1,
+                expected: 100,
+                actual: 112,
+            },
+            problems[2]
+        );
+        assert_eq!(3, problems.len());
+    }
+
+    #[test]
+    fn test_check_retention_too_long() {
+        let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 1);
+        let header = Header::new(metadata, vec![ArchiveInfo::new(28, 65536, 65536)]);
+        let mut bytes = vec![];
+        memento_encode_header(&mut bytes, &header).unwrap();
+
+        let report = check_at(&bytes, 1500000000);
+        let problems = report.problems();
+
+        match problems[0] {
+            Problem::InvalidArchives(kind, _) => assert_eq!(ErrorKind::InvalidRetention, kind),
+            ref p => panic!("unexpected problem: {:?}", p),
+        }
+        assert_eq!(
+            Problem::MaxRetention {
+                expected: 1 << 32,
+                actual: 0,
+            },
+            problems[1]
+        );
+    }
+
+    #[test]
+    fn test_check_invalid_archives() {
+        let mut bytes = get_database(vec![Point::default(); 5]);
+        // Seconds per point of the second archive
+        bytes[34] = 0;
+        bytes[35] = 60;
+
+        let problems = check_at(&bytes, 1500000000).problems().to_vec();
+
+        match problems[0] {
+            Problem::InvalidArchives(kind, _) => {
+                assert_eq!(ErrorKind::DuplicateArchivePrecision, kind)
+            }
+            ref p => panic!("unexpected problem {:?}", p),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Functions to compare the contents of two Whisper databases
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::Utc;
+
+use memento_core::types::{AggregationType, ArchiveInfo, MementoDatabase, Point};
+
+/// A difference between the headers of two databases.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderDiff {
+    /// The databases use different aggregation methods.
+    Aggregation(AggregationType, AggregationType),
+    /// The databases use different x-files-factors.
+    XFilesFactor(f32, f32),
+    /// The archive at the given index has a different precision or number
+    /// of points in each database or only exists in one of them.
+    Archive(usize, Option<ArchiveInfo>, Option<ArchiveInfo>),
+}
+
+/// A single interval of an archive that has a different value in each
+/// database or only has a value in one of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointDiff {
+    archive: usize,
+    timestamp: u32,
+    first: Option<f64>,
+    second: Option<f64>,
+}
+
+impl PointDiff {
+    pub fn new(archive: usize, timestamp: u32, first: Option<f64>, second: Option<f64>) -> Self {
+        PointDiff {
+            archive: archive,
+            timestamp: timestamp,
+            first: first,
+            second: second,
+        }
+    }
+
+    /// Index of the archive the interval belongs to.
+    pub fn archive(&self) -> usize {
+        self.archive
+    }
+
+    /// Start of the interval.
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    /// Value of the interval in the first database, if any.
+    pub fn first(&self) -> Option<f64> {
+        self.first
+    }
+
+    /// Value of the interval in the second database, if any.
+    pub fn second(&self) -> Option<f64> {
+        self.second
+    }
+}
+
+/// All differences found between two databases.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DatabaseDiff {
+    header: Vec<HeaderDiff>,
+    points: Vec<PointDiff>,
+}
+
+impl DatabaseDiff {
+    pub fn new(header: Vec<HeaderDiff>, points: Vec<PointDiff>) -> Self {
+        DatabaseDiff {
+            header: header,
+            points: points,
+        }
+    }
+
+    /// Differences between the headers of the databases.
+    pub fn header(&self) -> &[HeaderDiff] {
+        &self.header
+    }
+
+    /// Differences between the points of the databases, ordered by archive
+    /// and then by timestamp.
+    pub fn points(&self) -> &[PointDiff] {
+        &self.points
+    }
+
+    /// Return true if no differences were found.
+    pub fn is_empty(&self) -> bool {
+        self.header.is_empty() && self.points.is_empty()
+    }
+}
+
+/// Get the values of all points of an archive that fall within its retention
+/// (based on `now`) keyed by timestamp. Anything else is left over from a
+/// previous trip around the ring buffer or was never written.
+fn archive_values(info: &ArchiveInfo, points: &[Point], now: u32) -> BTreeMap<u32, f64> {
+    let oldest = u64::from(now).saturating_sub(info.retention()) as u32;
+
+    points
+        .iter()
+        .filter(|p| {
+            p.timestamp() > oldest
+                && p.timestamp() <= now
+                && p.timestamp() % info.seconds_per_point() == 0
+        })
+        .map(|p| (p.timestamp(), p.value()))
+        .collect()
+}
+
+/// Compare two databases as of `now`, see `diff`.
+fn diff_at(first: &MementoDatabase, second: &MementoDatabase, now: u32) -> DatabaseDiff {
+    let (meta1, meta2) = (first.header().metadata(), second.header().metadata());
+    let (infos1, infos2) = (
+        first.header().archive_info(),
+        second.header().archive_info(),
+    );
+    let mut header = Vec::new();
+    let mut points = Vec::new();
+
+    if meta1.aggregation() != meta2.aggregation() {
+        header.push(HeaderDiff::Aggregation(
+            meta1.aggregation(),
+            meta2.aggregation(),
+        ));
+    }
+
+    if meta1.x_files_factor() != meta2.x_files_factor() {
+        header.push(HeaderDiff::XFilesFactor(
+            meta1.x_files_factor(),
+            meta2.x_files_factor(),
+        ));
+    }
+
+    for i in 0..infos1.len().max(infos2.len()) {
+        let (info1, info2) = (infos1.get(i), infos2.get(i));
+        let same = match (info1, info2) {
+            (Some(a), Some(b)) => {
+                a.seconds_per_point() == b.seconds_per_point() && a.num_points() == b.num_points()
+            }
+            _ => false,
+        };
+
+        if !same {
+            header.push(HeaderDiff::Archive(i, info1.cloned(), info2.cloned()));
+        }
+
+        // Points can only be compared between archives with the same precision.
+        // When retention differs, only the period covered by both is compared.
+        let (info1, info2) = match (info1, info2) {
+            (Some(a), Some(b)) if a.seconds_per_point() == b.seconds_per_point() => (a, b),
+            _ => continue,
+        };
+
+        let covered = if info1.retention() < info2.retention() {
+            info1
+        } else {
+            info2
+        };
+
+        let values1 = archive_values(covered, first.data().archives()[i].points(), now);
+        let values2 = archive_values(covered, second.data().archives()[i].points(), now);
+        let timestamps: BTreeSet<u32> = values1.keys().chain(values2.keys()).cloned().collect();
+
+        for ts in timestamps {
+            let (v1, v2) = (values1.get(&ts).cloned(), values2.get(&ts).cloned());
+            // Bit patterns are compared so that NaN values stored in both
+            // databases are considered equal.
+            if v1.map(f64::to_bits) != v2.map(f64::to_bits) {
+                points.push(PointDiff::new(i, ts, v1, v2));
+            }
+        }
+    }
+
+    DatabaseDiff::new(header, points)
+}
+
+/// Compare two databases archive by archive, returning any differences
+/// between their headers and between the values of each interval of each
+/// archive.
+///
+/// Only intervals within the retention of an archive, based on the current
+/// time, are compared. Archives are compared by index and points of archives
+/// with different precisions are not compared at all. Offsets and maximum
+/// retention of the databases are not compared since they depend only on the
+/// archives.
+pub fn diff(first: &MementoDatabase, second: &MementoDatabase) -> DatabaseDiff {
+    diff_at(first, second, Utc::now().timestamp() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use memento_core::types::{
+        AggregationType, Archive, ArchiveInfo, Data, Header, MementoDatabase, Metadata, Point,
+    };
+
+    use super::{diff_at, HeaderDiff, PointDiff};
+
+    fn get_database(
+        aggregation: AggregationType,
+        infos: Vec<ArchiveInfo>,
+        archives: Vec<Vec<Point>>,
+    ) -> MementoDatabase {
+        let metadata = Metadata::new(aggregation, 0, 0.5, infos.len() as u32);
+        let archives = archives.into_iter().map(Archive::new).collect();
+        MementoDatabase::new(Header::new(metadata, infos), Data::new(archives))
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let now = 1500000000;
+        let infos = vec![ArchiveInfo::new(28, 60, 3)];
+        let points = vec![
+            Point::new(now - 60, 1.0),
+            Point::new(now, 2.0),
+            Point::new(0, 0.0),
+        ];
+        let db1 = get_database(
+            AggregationType::Average,
+            infos.clone(),
+            vec![points.clone()],
+        );
+        let db2 = get_database(AggregationType::Average, infos, vec![points]);
+
+        assert!(diff_at(&db1, &db2, now).is_empty());
+    }
+
+    #[test]
+    fn test_diff_nan() {
+        let now = 1500000000;
+        let infos = vec![ArchiveInfo::new(28, 60, 3)];
+        let db1 = get_database(
+            AggregationType::Average,
+            infos.clone(),
+            vec![vec![
+                Point::new(now - 60, f64::NAN),
+                Point::new(now, f64::NAN),
+            ]],
+        );
+        let db2 = get_database(
+            AggregationType::Average,
+            infos,
+            vec![vec![Point::new(now - 60, f64::NAN), Point::new(now, 1.0)]],
+        );
+
+        let diff = diff_at(&db1, &db2, now);
+        let points = diff.points();
+
+        assert_eq!(1, points.len());
+        assert_eq!(now, points[0].timestamp());
+        assert!(points[0].first().unwrap().is_nan());
+        assert_eq!(Some(1.0), points[0].second());
+    }
+
+    #[test]
+    fn test_diff_points() {
+        let now = 1500000000;
+        let infos = vec![ArchiveInfo::new(28, 60, 3)];
+        let db1 = get_database(
+            AggregationType::Average,
+            infos.clone(),
+            vec![vec![
+                Point::new(now - 120, 1.0),
+                Point::new(now - 60, 2.0),
+                // Outside of the retention of the archive, ignored
+                Point::new(now - 600, 3.0),
+            ]],
+        );
+        let db2 = get_database(
+            AggregationType::Average,
+            infos,
+            vec![vec![
+                Point::new(now - 120, 1.0),
+                Point::new(now - 60, 5.0),
+                Point::new(now, 6.0),
+            ]],
+        );
+
+        let diff = diff_at(&db1, &db2, now);
+
+        assert!(diff.header().is_empty());
+        assert_eq!(
+            &[
+                PointDiff::new(0, now - 60, Some(2.0), Some(5.0)),
+                PointDiff::new(0, now, None, Some(6.0)),
+            ],
+            diff.points()
+        );
+    }
+
+    #[test]
+    fn test_diff_header() {
+        let now = 1500000000;
+        let db1 = get_database(
+            AggregationType::Average,
+            vec![ArchiveInfo::new(40, 60, 3), ArchiveInfo::new(76, 300, 2)],
+            vec![vec![Point::new(now, 1.0)], vec![]],
+        );
+        let db2 = get_database(
+            AggregationType::Max,
+            vec![ArchiveInfo::new(28, 60, 5)],
+            vec![vec![Point::new(now, 1.0), Point::new(now - 240, 2.0)]],
+        );
+
+        let diff = diff_at(&db1, &db2, now);
+
+        assert_eq!(
+            &[
+                HeaderDiff::Aggregation(AggregationType::Average, AggregationType::Max),
+                HeaderDiff::Archive(
+                    0,
+                    Some(ArchiveInfo::new(40, 60, 3)),
+                    Some(ArchiveInfo::new(28, 60, 5)),
+                ),
+                HeaderDiff::Archive(1, Some(ArchiveInfo::new(76, 300, 2)), None),
+            ],
+            diff.header()
+        );
+
+        // Only the period covered by both archives is compared
+        assert!(diff.points().is_empty());
+    }
+}
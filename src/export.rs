@@ -0,0 +1,264 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Functions to export fetched values and whole databases as CSV or JSON
+
+use std::io::{self, Write};
+
+use serde_json::{self, Value};
+
+use memento_core::types::{Archive, ArchiveInfo, MementoDatabase};
+
+use read::FetchResponse;
+
+/// Convert an archive to the values of each interval from the oldest to
+/// the newest point of it.
+///
+/// Only points within the retention of the archive, counting back from the
+/// newest point, are included. Like a fetch, slots left over from a previous
+/// trip around the archive or with an unaligned timestamp are ignored and
+/// intervals without a value are `None`. An archive that has never been
+/// written to results in an empty range.
+fn archive_to_response(info: &ArchiveInfo, archive: &Archive) -> FetchResponse {
+    let step = info.seconds_per_point();
+    let valid = || {
+        archive
+            .points()
+            .iter()
+            .filter(move |p| p.timestamp() != 0 && p.timestamp() % step == 0)
+    };
+
+    let newest = match valid().map(|p| p.timestamp()).max() {
+        Some(ts) => ts,
+        None => return FetchResponse::new(info.clone(), 0, 0, Vec::new()),
+    };
+
+    let from = newest.saturating_sub((info.num_points() - 1).saturating_mul(step));
+    let mut values = vec![None; ((newest - from) / step + 1) as usize];
+    for point in valid().filter(|p| p.timestamp() >= from) {
+        values[((point.timestamp() - from) / step) as usize] = Some(point.value());
+    }
+
+    FetchResponse::new(info.clone(), from, newest + step, values)
+}
+
+/// Convert each archive of a database to the values of each interval from
+/// the oldest to the newest point of it, in the same order as the archives.
+///
+/// Points of each archive are placed into intervals in the same way as a
+/// fetch: slots left over from a previous trip around the archive are
+/// ignored and intervals without a value are `None`.
+pub fn database_to_responses(database: &MementoDatabase) -> Vec<FetchResponse> {
+    database
+        .header()
+        .archive_info()
+        .iter()
+        .zip(database.data().archives())
+        .map(|(info, archive)| archive_to_response(info, archive))
+        .collect()
+}
+
+/// Write the timestamp and value of each interval of a response as a CSV row.
+fn write_csv_rows<W>(writer: &mut W, prefix: &str, res: &FetchResponse) -> io::Result<()>
+where
+    W: Write,
+{
+    for (i, value) in res.values().iter().enumerate() {
+        let timestamp = res.from() + i as u32 * res.step();
+        match *value {
+            Some(v) => writeln!(writer, "{}{},{}", prefix, timestamp, v)?,
+            None => writeln!(writer, "{}{},", prefix, timestamp)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the values of a response as CSV with a `timestamp,value` header
+/// row followed by a row for each interval. Intervals without a value have
+/// an empty value.
+pub fn write_csv<W>(writer: &mut W, res: &FetchResponse) -> io::Result<()>
+where
+    W: Write,
+{
+    writeln!(writer, "timestamp,value")?;
+    write_csv_rows(writer, "", res)
+}
+
+/// Write every archive of a database as CSV with an `archive,timestamp,value`
+/// header row followed by a row for each interval of each archive (see
+/// `database_to_responses`). Intervals without a value have an empty value.
+pub fn write_database_csv<W>(writer: &mut W, database: &MementoDatabase) -> io::Result<()>
+where
+    W: Write,
+{
+    writeln!(writer, "archive,timestamp,value")?;
+    for (i, res) in database_to_responses(database).iter().enumerate() {
+        write_csv_rows(writer, &format!("{},", i), res)?;
+    }
+
+    Ok(())
+}
+
+/// Convert a response to a single series in the Graphite render format.
+fn series_to_json(target: &str, res: &FetchResponse) -> Value {
+    let datapoints: Vec<Value> = res
+        .values()
+        .iter()
+        .enumerate()
+        .map(|(i, v)| json!([v, res.from() + i as u32 * res.step()]))
+        .collect();
+
+    json!({
+        "target": target,
+        "datapoints": datapoints,
+    })
+}
+
+/// Write the values of a response as JSON in the format returned by the
+/// Graphite render API (`[{"target": ..., "datapoints": [[value, timestamp],
+/// ...]}]`). Intervals without a value, and values that can't be represented
+/// in JSON (NaN and infinity), are `null`.
+pub fn write_graphite_json<W>(writer: &mut W, target: &str, res: &FetchResponse) -> io::Result<()>
+where
+    W: Write,
+{
+    let series = json!([series_to_json(target, res)]);
+    Ok(serde_json::to_writer(writer, &series)?)
+}
+
+/// Write every archive of a database as JSON in the format returned by the
+/// Graphite render API with a series for each archive (see
+/// `database_to_responses`). The target of each series is `target` tagged
+/// with the index of the archive, e.g. `foo.bar;archive=0`.
+pub fn write_database_graphite_json<W>(
+    writer: &mut W,
+    target: &str,
+    database: &MementoDatabase,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let series: Vec<Value> = database_to_responses(database)
+        .iter()
+        .enumerate()
+        .map(|(i, res)| series_to_json(&format!("{};archive={}", target, i), res))
+        .collect();
+
+    Ok(serde_json::to_writer(writer, &series)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64;
+
+    use memento_core::types::{
+        AggregationType, Archive, ArchiveInfo, Data, Header, MementoDatabase, Metadata, Point,
+    };
+
+    use read::FetchResponse;
+
+    use super::{
+        database_to_responses, write_csv, write_database_csv, write_database_graphite_json,
+        write_graphite_json,
+    };
+
+    fn get_response() -> FetchResponse {
+        let values = vec![Some(1.5), None, Some(f64::NAN)];
+        FetchResponse::new(
+            ArchiveInfo::new(28, 60, 1440),
+            1500000000,
+            1500000180,
+            values,
+        )
+    }
+
+    fn get_database() -> MementoDatabase {
+        let metadata = Metadata::new(AggregationType::Average, 1200, 0.5, 2);
+        let info1 = ArchiveInfo::new(40, 60, 4);
+        let info2 = ArchiveInfo::new(88, 300, 4);
+
+        let archive1 = Archive::new(vec![
+            Point::new(1500000000, 1.0),
+            // Left over from a previous trip around the archive
+            Point::new(1499999700, 2.0),
+            Point::new(1500000120, 3.0),
+            Point::new(1500000180, 4.0),
+        ]);
+        let archive2 = Archive::new(vec![Point::default(); 4]);
+
+        MementoDatabase::new(
+            Header::new(metadata, vec![info1, info2]),
+            Data::new(vec![archive1, archive2]),
+        )
+    }
+
+    #[test]
+    fn test_database_to_responses() {
+        let responses = database_to_responses(&get_database());
+
+        assert_eq!(2, responses.len());
+        assert_eq!(1500000000, responses[0].from());
+        assert_eq!(1500000240, responses[0].until());
+        assert_eq!(
+            &[Some(1.0), None, Some(3.0), Some(4.0)],
+            responses[0].values()
+        );
+        assert!(responses[1].values().is_empty());
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut buf = vec![];
+        write_csv(&mut buf, &get_response()).unwrap();
+
+        assert_eq!(
+            "timestamp,value\n1500000000,1.5\n1500000060,\n1500000120,NaN\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_write_database_csv() {
+        let mut buf = vec![];
+        write_database_csv(&mut buf, &get_database()).unwrap();
+
+        assert_eq!(
+            "archive,timestamp,value\n\
+             0,1500000000,1\n\
+             0,1500000060,\n\
+             0,1500000120,3\n\
+             0,1500000180,4\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_write_graphite_json() {
+        let mut buf = vec![];
+        write_graphite_json(&mut buf, "foo.bar", &get_response()).unwrap();
+
+        assert_eq!(
+            r#"[{"datapoints":[[1.5,1500000000],[null,1500000060],[null,1500000120]],"target":"foo.bar"}]"#,
+            String::from_utf8(buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_write_database_graphite_json() {
+        let mut buf = vec![];
+        write_database_graphite_json(&mut buf, "foo.bar", &get_database()).unwrap();
+
+        assert_eq!(
+            r#"[{"datapoints":[[1.0,1500000000],[null,1500000060],[3.0,1500000120],[4.0,1500000180]],"target":"foo.bar;archive=0"},{"datapoints":[],"target":"foo.bar;archive=1"}]"#,
+            String::from_utf8(buf).unwrap()
+        );
+    }
+}
@@ -0,0 +1,413 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Long lived handles to Whisper files for serving repeated requests
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use memmap::Mmap;
+
+use io::SliceReaderMapped;
+use lock::{lock_shared, unlock, LockMode};
+use memento_core::errors::{MementoError, MementoResult};
+use memento_core::types::Header;
+use read::{FetchRequest, FetchResponse, MementoParser};
+
+/// Properties of a file used to tell if it has been modified or replaced
+/// since it was opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileIdentity {
+    len: u64,
+    modified: Option<SystemTime>,
+    inode: Option<(u64, u64)>,
+}
+
+impl FileIdentity {
+    #[cfg(unix)]
+    fn new(meta: &fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        FileIdentity {
+            len: meta.len(),
+            modified: meta.modified().ok(),
+            inode: Some((meta.dev(), meta.ino())),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn new(meta: &fs::Metadata) -> Self {
+        FileIdentity {
+            len: meta.len(),
+            modified: meta.modified().ok(),
+            inode: None,
+        }
+    }
+}
+
+/// An open, memory mapped, whisper database file and its parsed header
+/// that can be used to serve multiple requests without reopening the file.
+///
+/// Updates made to the file in place are visible to the handle but changes
+/// to the header, or the file being replaced, are not. Use `is_modified` to
+/// check if the handle should be reopened.
+#[derive(Debug)]
+pub struct MementoDatabaseHandle {
+    path: PathBuf,
+    file: File,
+    reader: SliceReaderMapped,
+    header: Header,
+    identity: FileIdentity,
+    lock: LockMode,
+}
+
+impl MementoDatabaseHandle {
+    /// Open and map a whisper database file and parse its header. A shared
+    /// lock is acquired based on the given locking mode while the header is
+    /// parsed and while each request is read.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if there were any I/O errors opening the
+    /// database file (such as permission errors), if the header was
+    /// malformed, or if it could not be locked.
+    pub fn open<P>(path: P, lock: LockMode) -> MementoResult<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        Self::open_file(path, lock).map_err(|e| e.with_path(path))
+    }
+
+    fn open_file(path: &Path, lock: LockMode) -> MementoResult<Self> {
+        let file = File::open(path)?;
+        let identity = FileIdentity::new(&file.metadata()?);
+
+        lock_shared(&file, lock)?;
+        let res = Self::read_header(&file);
+        unlock(&file, lock)?;
+        let (reader, header) = res?;
+
+        Ok(MementoDatabaseHandle {
+            path: path.to_path_buf(),
+            file: file,
+            reader: reader,
+            header: header,
+            identity: identity,
+            lock: lock,
+        })
+    }
+
+    fn read_header(file: &File) -> MementoResult<(SliceReaderMapped, Header)> {
+        let map = unsafe { Mmap::map(file)? };
+        let mut reader = SliceReaderMapped::new(map);
+        let header = MementoParser::new(&mut reader).read_header()?;
+        Ok((reader, header))
+    }
+
+    /// Path the database file was opened from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Header of the database file as of when it was opened.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Read a portion of the database file based on the given request.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if the file could not be locked, if it was
+    /// malformed, or if the request could not be fulfilled by this database
+    /// file.
+    pub fn read(&mut self, req: &FetchRequest) -> MementoResult<FetchResponse> {
+        let (file, lock) = (&self.file, self.lock);
+        let (reader, header) = (&mut self.reader, &self.header);
+
+        let res = lock_shared(file, lock).and_then(|_| {
+            let res = MementoParser::new(reader).read_range_with_header(header, req);
+            unlock(file, lock)?;
+            res
+        });
+
+        res.map_err(|e| e.with_path(&self.path))
+    }
+
+    /// Return true if the file at the path this handle was opened from has
+    /// been modified or replaced since it was opened.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if the file no longer exists or could not be
+    /// accessed.
+    pub fn is_modified(&self) -> MementoResult<bool> {
+        let meta =
+            fs::metadata(&self.path).map_err(|e| MementoError::from(e).with_path(&self.path))?;
+        Ok(FileIdentity::new(&meta) != self.identity)
+    }
+}
+
+/// Pool of open database handles keyed by path, limited to a maximum number
+/// of handles (and hence file descriptors and mappings).
+///
+/// When the pool is full, the least recently used handle is closed to make
+/// room for a new one. Handles are reopened when the file they were opened
+/// from has been modified or replaced.
+#[derive(Debug)]
+pub struct MementoHandlePool {
+    capacity: usize,
+    lock: LockMode,
+    handles: HashMap<PathBuf, MementoDatabaseHandle>,
+    order: VecDeque<PathBuf>,
+}
+
+impl MementoHandlePool {
+    /// Create a new pool that keeps at most `capacity` handles open (at
+    /// least one) and doesn't lock files.
+    pub fn new(capacity: usize) -> Self {
+        MementoHandlePool {
+            capacity: capacity.max(1),
+            lock: LockMode::None,
+            handles: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Use the given locking mode for handles opened by this pool.
+    pub fn with_lock(mut self, val: LockMode) -> Self {
+        self.lock = val;
+        self
+    }
+
+    /// Maximum number of handles kept open.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of handles currently open.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Return true if there are no handles open.
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Return true if there is a handle open for the given path.
+    pub fn contains<P>(&self, path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        self.handles.contains_key(path.as_ref())
+    }
+
+    /// Close the handle for the given path, returning true if there was one.
+    pub fn remove<P>(&mut self, path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        self.order.retain(|p| p != path);
+        self.handles.remove(path).is_some()
+    }
+
+    /// Close all handles.
+    pub fn clear(&mut self) {
+        self.order.clear();
+        self.handles.clear();
+    }
+
+    /// Get the handle for the given path, opening it (and closing the least
+    /// recently used handle if the pool is full) if it isn't open or if the
+    /// file has been modified or replaced.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if the file no longer exists or if it could
+    /// not be opened (see `MementoDatabaseHandle::open`). Any existing handle
+    /// for the path is closed in this case.
+    pub fn get<P>(&mut self, path: P) -> MementoResult<&mut MementoDatabaseHandle>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        let current = match self.handles.get(&path).map(|h| h.is_modified()) {
+            Some(Ok(modified)) => !modified,
+            Some(Err(e)) => {
+                self.remove(&path);
+                return Err(e);
+            }
+            None => false,
+        };
+
+        if current {
+            self.order.retain(|p| *p != path);
+        } else {
+            self.remove(&path);
+            let handle = MementoDatabaseHandle::open(&path, self.lock)?;
+
+            while self.handles.len() >= self.capacity {
+                match self.order.pop_front() {
+                    Some(oldest) => self.handles.remove(&oldest),
+                    None => break,
+                };
+            }
+
+            self.handles.insert(path.clone(), handle);
+        }
+
+        self.order.push_back(path.clone());
+        Ok(self
+            .handles
+            .get_mut(&path)
+            .expect("handle missing from pool"))
+    }
+
+    /// Read a portion of the database file at the given path based on the
+    /// given request using a handle from this pool.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if the handle could not be opened (see `get`)
+    /// or if the request could not be read (see `MementoDatabaseHandle::read`).
+    pub fn read<P>(&mut self, path: P, req: &FetchRequest) -> MementoResult<FetchResponse>
+    where
+        P: AsRef<Path>,
+    {
+        self.get(path)?.read(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use chrono::{TimeZone, Utc};
+    use tempfile::TempDir;
+
+    use lock::LockMode;
+    use memento_core::errors::ErrorKind;
+    use memento_core::types::{AggregationType, ArchiveInfo, Header, Metadata};
+    use read::{FetchRequest, MementoFileReader};
+    use write::MementoFileWriter;
+
+    use super::{MementoDatabaseHandle, MementoHandlePool};
+
+    fn create_database(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 0);
+        let header = Header::new(metadata, vec![ArchiveInfo::new(0, 60, 1440)]);
+        MementoFileWriter::new().create(&path, &header).unwrap();
+        path
+    }
+
+    fn request(now: i64) -> FetchRequest {
+        FetchRequest::default()
+            .with_from(Utc.timestamp(now - 600, 0))
+            .with_until(Utc.timestamp(now, 0))
+    }
+
+    #[test]
+    fn test_handle_read_sees_updates() {
+        let dir = TempDir::new().unwrap();
+        let path = create_database(dir.path(), "handle.wsp");
+        let mut handle = MementoDatabaseHandle::open(&path, LockMode::NonBlocking).unwrap();
+        let now = Utc::now().timestamp();
+        let req = request(now);
+
+        assert!(handle.read(&req).unwrap().points().is_empty());
+
+        // Written at a fixed time inside the requested range so that the
+        // point can't end up past `until` if the clock moves to a new interval.
+        MementoFileWriter::new()
+            .update(&path, 42.0, Utc.timestamp(now - 300, 0))
+            .unwrap();
+
+        let expected = MementoFileReader::new().read(&path, &req).unwrap();
+        assert_eq!(expected, handle.read(&req).unwrap());
+        assert_eq!(1, expected.points().len());
+    }
+
+    #[test]
+    fn test_handle_is_modified() {
+        let dir = TempDir::new().unwrap();
+        let path = create_database(dir.path(), "modified.wsp");
+        let other = create_database(dir.path(), "other.wsp");
+        let handle = MementoDatabaseHandle::open(&path, LockMode::None).unwrap();
+
+        assert!(!handle.is_modified().unwrap());
+
+        fs::rename(&other, &path).unwrap();
+        assert!(handle.is_modified().unwrap());
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(ErrorKind::IoError, handle.is_modified().unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_pool_evicts_least_recently_used() {
+        let dir = TempDir::new().unwrap();
+        let first = create_database(dir.path(), "first.wsp");
+        let second = create_database(dir.path(), "second.wsp");
+        let third = create_database(dir.path(), "third.wsp");
+        let mut pool = MementoHandlePool::new(2);
+        let req = request(Utc::now().timestamp());
+
+        pool.read(&first, &req).unwrap();
+        pool.read(&second, &req).unwrap();
+        pool.read(&first, &req).unwrap();
+        pool.read(&third, &req).unwrap();
+
+        assert_eq!(2, pool.len());
+        assert!(pool.contains(&first));
+        assert!(!pool.contains(&second));
+        assert!(pool.contains(&third));
+    }
+
+    #[test]
+    fn test_pool_reopens_modified_file() {
+        let dir = TempDir::new().unwrap();
+        let path = create_database(dir.path(), "reopen.wsp");
+        let mut pool = MementoHandlePool::new(4);
+
+        assert_eq!(
+            AggregationType::Average,
+            pool.get(&path).unwrap().header().metadata().aggregation()
+        );
+
+        MementoFileWriter::new()
+            .set_aggregation(&path, AggregationType::Max)
+            .unwrap();
+
+        assert_eq!(
+            AggregationType::Max,
+            pool.get(&path).unwrap().header().metadata().aggregation()
+        );
+    }
+
+    #[test]
+    fn test_pool_removes_missing_file() {
+        let dir = TempDir::new().unwrap();
+        let path = create_database(dir.path(), "missing.wsp");
+        let mut pool = MementoHandlePool::new(4);
+
+        pool.get(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(ErrorKind::IoError, pool.get(&path).unwrap_err().kind());
+        assert!(pool.is_empty());
+    }
+}
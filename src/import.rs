@@ -0,0 +1,417 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Functions to read points exported as CSV or JSON for importing them
+//! into a database
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read};
+
+use serde_json::{self, Value};
+
+use memento_core::errors::{ErrorKind, MementoError, MementoResult};
+use memento_core::types::Point;
+
+/// How imported points are written to a database that already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Replace any existing value for an interval.
+    Overwrite,
+    /// Only write intervals that don't have a value yet.
+    Fill,
+    /// Leave the database unchanged, only write points to new databases.
+    Skip,
+}
+
+/// Read points from CSV with a `timestamp,value` row for each point, as
+/// written by `export::write_csv`.
+///
+/// CSV with an `archive,timestamp,value` row for each point, as written by
+/// `export::write_database_csv`, is also accepted. The rows of every archive
+/// are read and each interval uses the highest precision archive that has
+/// it (see `merge_archives`).
+///
+/// The first row is treated as a header and ignored if its timestamp isn't
+/// a number. Blank lines and rows with an empty value (gaps) are ignored.
+///
+/// # Errors
+///
+/// Return an error if reading fails or if any row doesn't have the same
+/// number of fields (two or three) as the first row, an archive index that
+/// is a number, a timestamp that is a Unix timestamp, and a value that is a
+/// number.
+pub fn read_csv<R>(reader: R) -> MementoResult<Vec<Point>>
+where
+    R: BufRead,
+{
+    let mut archives: BTreeMap<usize, ArchiveSeries> = BTreeMap::new();
+    let mut columns = None;
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let row = line.trim();
+        if row.is_empty() {
+            continue;
+        }
+
+        let invalid = || {
+            MementoError::from((
+                ErrorKind::ParseError,
+                "invalid CSV row",
+                format!("line {}: {}", i + 1, row),
+            ))
+        };
+
+        let mut fields: Vec<&str> = row.split(',').map(str::trim).collect();
+        let count = *columns.get_or_insert(fields.len());
+        if fields.len() != count || (count != 2 && count != 3) {
+            return Err(invalid());
+        }
+
+        // Whole database exports have the index of the archive first
+        let archive = if count == 3 {
+            Some(fields.remove(0))
+        } else {
+            None
+        };
+
+        let timestamp = match fields[0].parse::<u32>() {
+            Ok(ts) => ts,
+            Err(_) if i == 0 => continue,
+            Err(_) => return Err(invalid()),
+        };
+
+        let index = match archive {
+            Some(archive) => archive.parse::<usize>().map_err(|_| invalid())?,
+            None => 0,
+        };
+
+        let series = archives.entry(index).or_default();
+        series.cover(timestamp);
+
+        if fields[1].is_empty() {
+            continue;
+        }
+
+        let value = fields[1].parse::<f64>().map_err(|_| invalid())?;
+        series.points.push(Point::new(timestamp, value));
+    }
+
+    Ok(merge_archives(archives.into_values()))
+}
+
+/// Points read for a single archive of an exported database along with the
+/// oldest interval exported for it, including intervals without a value.
+#[derive(Debug, Default)]
+struct ArchiveSeries {
+    start: Option<u32>,
+    points: Vec<Point>,
+}
+
+impl ArchiveSeries {
+    /// Record that the interval starting at `timestamp` was exported.
+    fn cover(&mut self, timestamp: u32) {
+        self.start = Some(self.start.map_or(timestamp, |start| start.min(timestamp)));
+    }
+}
+
+/// Combine the points of each archive of an exported database, from highest
+/// to lowest precision, into a single series ordered by timestamp.
+///
+/// Lower precision archives only contribute points older than every interval
+/// exported for the higher precision archives before them, so each interval
+/// uses the highest precision data available for it instead of data that was
+/// aggregated from it.
+fn merge_archives<I>(archives: I) -> Vec<Point>
+where
+    I: IntoIterator<Item = ArchiveSeries>,
+{
+    let mut points = Vec::new();
+    let mut covered: Option<u32> = None;
+
+    for series in archives {
+        points.extend(
+            series
+                .points
+                .into_iter()
+                .filter(|p| covered.is_none_or(|start| p.timestamp() < start)),
+        );
+
+        covered = match (covered, series.start) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    // Archives are merged from newest to oldest data so sort them back into
+    // order, keeping the order of points within each archive.
+    points.sort_by_key(Point::timestamp);
+    points
+}
+
+/// Get the points of a single series in the Graphite render format, skipping
+/// any `null` values. Return `None` if the series is malformed.
+fn series_to_points(series: &Value) -> Option<ArchiveSeries> {
+    let mut archive = ArchiveSeries::default();
+
+    for datapoint in series.get("datapoints")?.as_array()? {
+        let pair = datapoint.as_array()?;
+        if pair.len() != 2 {
+            return None;
+        }
+
+        let timestamp = pair[1].as_u64().filter(|&ts| ts <= u64::from(u32::MAX))?;
+        archive.cover(timestamp as u32);
+        if pair[0].is_null() {
+            continue;
+        }
+
+        archive
+            .points
+            .push(Point::new(timestamp as u32, pair[0].as_f64()?));
+    }
+
+    Some(archive)
+}
+
+/// Get the index of the archive a series of a whole database export is for
+/// from the `archive=N` tag of its target.
+fn series_archive(series: &Value) -> Option<usize> {
+    let target = series.get("target")?.as_str()?;
+    let tag = target.rsplit(';').next()?;
+    if !tag.starts_with("archive=") {
+        return None;
+    }
+
+    tag["archive=".len()..].parse().ok()
+}
+
+/// Read the points of a single series from JSON in the format returned by
+/// the Graphite render API (`[{"target": ..., "datapoints": [[value,
+/// timestamp], ...]}]`), as written by `export::write_graphite_json`.
+///
+/// JSON with a series for each archive of a database, as written by
+/// `export::write_database_graphite_json`, is also accepted. Each series must
+/// have a target tagged with the index of its archive (`archive=N`) and each
+/// interval uses the highest precision archive that has it (see
+/// `merge_archives`). Datapoints with a `null` value (gaps) are ignored.
+///
+/// # Errors
+///
+/// Return an error if reading fails, if the input isn't valid JSON, if it
+/// doesn't contain exactly one series or a series for each archive of a
+/// database, or if any datapoint isn't a pair of a number (or `null`) and a
+/// Unix timestamp.
+pub fn read_graphite_json<R>(reader: R) -> MementoResult<Vec<Point>>
+where
+    R: Read,
+{
+    let doc: Value = serde_json::from_reader(reader)
+        .map_err(|e| MementoError::from((ErrorKind::ParseError, "invalid JSON", e.to_string())))?;
+
+    let invalid_list = || {
+        MementoError::from((
+            ErrorKind::ParseError,
+            "expected a list with a single series or a series for each archive",
+        ))
+    };
+    let invalid_series = || MementoError::from((ErrorKind::ParseError, "invalid Graphite series"));
+
+    let series = match doc.as_array() {
+        Some(series) if !series.is_empty() => series,
+        _ => return Err(invalid_list()),
+    };
+
+    if series.len() == 1 {
+        return series_to_points(&series[0])
+            .map(|archive| archive.points)
+            .ok_or_else(invalid_series);
+    }
+
+    let mut archives = BTreeMap::new();
+    for s in series {
+        let index = series_archive(s).ok_or_else(invalid_list)?;
+        let archive = series_to_points(s).ok_or_else(invalid_series)?;
+        if archives.insert(index, archive).is_some() {
+            return Err(invalid_list());
+        }
+    }
+
+    Ok(merge_archives(archives.into_values()))
+}
+
+#[cfg(test)]
+mod tests {
+    use memento_core::errors::ErrorKind;
+    use memento_core::types::{
+        AggregationType, Archive, ArchiveInfo, Data, Header, MementoDatabase, Metadata, Point,
+    };
+
+    use export::{write_database_csv, write_database_graphite_json};
+
+    use super::{read_csv, read_graphite_json};
+
+    fn get_database() -> MementoDatabase {
+        let metadata = Metadata::new(AggregationType::Average, 600, 0.5, 2);
+        let info1 = ArchiveInfo::new(40, 60, 3);
+        let info2 = ArchiveInfo::new(76, 300, 2);
+
+        let archive1 = Archive::new(vec![
+            Point::new(1500000060, 2.0),
+            Point::new(1500000000, 1.0),
+            Point::default(),
+        ]);
+        let archive2 = Archive::new(vec![Point::new(1500000000, 1.5), Point::default()]);
+
+        MementoDatabase::new(
+            Header::new(metadata, vec![info1, info2]),
+            Data::new(vec![archive1, archive2]),
+        )
+    }
+
+    /// Database with older points only in the lower precision archive.
+    fn get_coarse_database() -> MementoDatabase {
+        let metadata = Metadata::new(AggregationType::Average, 600, 0.5, 2);
+        let info1 = ArchiveInfo::new(40, 60, 3);
+        let info2 = ArchiveInfo::new(76, 300, 3);
+
+        let archive1 = Archive::new(vec![
+            Point::new(1500000600, 3.0),
+            Point::default(),
+            Point::default(),
+        ]);
+        let archive2 = Archive::new(vec![
+            Point::new(1500000000, 1.5),
+            Point::new(1500000300, 2.5),
+            Point::new(1500000600, 2.0),
+        ]);
+
+        MementoDatabase::new(
+            Header::new(metadata, vec![info1, info2]),
+            Data::new(vec![archive1, archive2]),
+        )
+    }
+
+    #[test]
+    fn test_read_csv() {
+        let csv = "timestamp,value\n1500000000,1.5\n1500000060,\n\n1500000120, 2\n";
+        let points = read_csv(csv.as_bytes()).unwrap();
+
+        assert_eq!(
+            vec![Point::new(1500000000, 1.5), Point::new(1500000120, 2.0)],
+            points
+        );
+    }
+
+    #[test]
+    fn test_read_csv_invalid_row() {
+        let res = read_csv("1500000000,1.5\nfoo,2\n".as_bytes());
+        let err = res.unwrap_err();
+
+        assert_eq!(ErrorKind::ParseError, err.kind());
+        assert!(err.to_string().contains("line 2"));
+
+        let res = read_csv("0,1500000000,1.5,2\n".as_bytes());
+        assert_eq!(ErrorKind::ParseError, res.unwrap_err().kind());
+
+        let res = read_csv("1500000000,1.5\n0,1500000060,2\n".as_bytes());
+        assert_eq!(ErrorKind::ParseError, res.unwrap_err().kind());
+
+        let res = read_csv("archive,timestamp,value\nfoo,1500000000,1.5\n".as_bytes());
+        assert_eq!(ErrorKind::ParseError, res.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_read_csv_database_export() {
+        let mut buf = Vec::new();
+        write_database_csv(&mut buf, &get_database()).unwrap();
+        let points = read_csv(&buf[..]).unwrap();
+
+        assert_eq!(
+            vec![Point::new(1500000000, 1.0), Point::new(1500000060, 2.0)],
+            points
+        );
+    }
+
+    #[test]
+    fn test_read_csv_database_export_coarse() {
+        let mut buf = Vec::new();
+        write_database_csv(&mut buf, &get_coarse_database()).unwrap();
+        let points = read_csv(&buf[..]).unwrap();
+
+        assert_eq!(
+            vec![
+                Point::new(1500000000, 1.5),
+                Point::new(1500000300, 2.5),
+                Point::new(1500000600, 3.0),
+            ],
+            points
+        );
+    }
+
+    #[test]
+    fn test_read_graphite_json() {
+        let json = r#"[{"target": "foo.bar", "datapoints": [[1.5, 1500000000], [null, 1500000060], [2, 1500000120]]}]"#;
+        let points = read_graphite_json(json.as_bytes()).unwrap();
+
+        assert_eq!(
+            vec![Point::new(1500000000, 1.5), Point::new(1500000120, 2.0)],
+            points
+        );
+    }
+
+    #[test]
+    fn test_read_graphite_json_invalid() {
+        let res = read_graphite_json("[{".as_bytes());
+        assert_eq!(ErrorKind::ParseError, res.unwrap_err().kind());
+
+        let res = read_graphite_json("[]".as_bytes());
+        assert_eq!(ErrorKind::ParseError, res.unwrap_err().kind());
+
+        let res = read_graphite_json(r#"[{"datapoints": [[1.5]]}]"#.as_bytes());
+        assert_eq!(ErrorKind::ParseError, res.unwrap_err().kind());
+
+        let json = r#"[{"target": "foo", "datapoints": []}, {"target": "bar", "datapoints": []}]"#;
+        let res = read_graphite_json(json.as_bytes());
+        assert_eq!(ErrorKind::ParseError, res.unwrap_err().kind());
+
+        let json = r#"[{"target": "foo;archive=0", "datapoints": []}, {"target": "foo;archive=0", "datapoints": []}]"#;
+        let res = read_graphite_json(json.as_bytes());
+        assert_eq!(ErrorKind::ParseError, res.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_read_graphite_json_database_export() {
+        let mut buf = Vec::new();
+        write_database_graphite_json(&mut buf, "foo.bar", &get_database()).unwrap();
+        let points = read_graphite_json(&buf[..]).unwrap();
+
+        assert_eq!(
+            vec![Point::new(1500000000, 1.0), Point::new(1500000060, 2.0)],
+            points
+        );
+    }
+
+    #[test]
+    fn test_read_graphite_json_database_export_coarse() {
+        let mut buf = Vec::new();
+        write_database_graphite_json(&mut buf, "foo.bar", &get_coarse_database()).unwrap();
+        let points = read_graphite_json(&buf[..]).unwrap();
+
+        assert_eq!(
+            vec![
+                Point::new(1500000000, 1.5),
+                Point::new(1500000300, 2.5),
+                Point::new(1500000600, 3.0),
+            ],
+            points
+        );
+    }
+}
@@ -90,6 +90,19 @@ impl SliceReaderMapped {
     }
 
     fn read_range<F, T>(&mut self, offset: u64, len: Option<u64>, consumer: F) -> MementoResult<T>
+    where
+        F: Fn(&[u8]) -> MementoResult<T>,
+    {
+        self.read_range_unchecked(offset, len, consumer)
+            .map_err(|e| e.with_offset(offset))
+    }
+
+    fn read_range_unchecked<F, T>(
+        &mut self,
+        offset: u64,
+        len: Option<u64>,
+        consumer: F,
+    ) -> MementoResult<T>
     where
         F: Fn(&[u8]) -> MementoResult<T>,
     {
@@ -180,6 +193,19 @@ impl SliceReaderDirect {
     }
 
     fn read_range<F, T>(&mut self, offset: u64, len: Option<u64>, consumer: F) -> MementoResult<T>
+    where
+        F: Fn(&[u8]) -> MementoResult<T>,
+    {
+        self.read_range_unchecked(offset, len, consumer)
+            .map_err(|e| e.with_offset(offset))
+    }
+
+    fn read_range_unchecked<F, T>(
+        &mut self,
+        offset: u64,
+        len: Option<u64>,
+        consumer: F,
+    ) -> MementoResult<T>
     where
         F: Fn(&[u8]) -> MementoResult<T>,
     {
@@ -336,6 +362,7 @@ mod tests {
 
         let err = res.unwrap_err();
         assert_eq!(ErrorKind::IoError, err.kind());
+        assert_eq!(Some(2), err.offset());
     }
 
     #[test]
@@ -408,5 +435,6 @@ mod tests {
 
         let err = res.unwrap_err();
         assert_eq!(ErrorKind::IoError, err.kind());
+        assert_eq!(Some(2), err.offset());
     }
 }
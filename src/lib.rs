@@ -8,16 +8,34 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+extern crate byteorder;
 extern crate chrono;
 extern crate fs2;
 extern crate memento_core;
 extern crate memmap;
+#[macro_use]
+extern crate serde_json;
+#[cfg(test)]
+extern crate tempfile;
 
+pub mod carbon;
+pub mod check;
+pub mod diff;
+pub mod export;
+mod handle;
+pub mod import;
 mod io;
+mod lock;
 mod read;
+pub mod repair;
 mod write;
 
+pub use handle::{MementoDatabaseHandle, MementoHandlePool};
 pub use io::{SeekRead, SliceReader, SliceReaderDirect, SliceReaderMapped};
+pub use lock::LockMode;
 pub use memento_core::errors;
+pub use memento_core::retention;
 pub use memento_core::types;
+pub use memento_core::view;
 pub use read::{FetchRequest, FetchResponse, MementoFileReader, MementoParser};
+pub use write::MementoFileWriter;
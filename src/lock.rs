@@ -0,0 +1,214 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Functions to acquire advisory locks on Whisper files
+
+use std::fs::File;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fs2::{self, FileExt};
+
+use memento_core::errors::{ErrorKind, MementoError, MementoResult};
+
+// How long to wait between attempts to acquire a lock when using a timeout.
+const LOCK_RETRY_INTERVAL_MS: u64 = 10;
+
+/// How files should be locked while they are being read or written.
+///
+/// Locks are advisory (`flock` on Unix systems) and are held until the file
+/// is closed. Readers acquire shared locks and writers acquire exclusive locks
+/// so that readers never observe a partially written update. Locking only
+/// protects against other processes or threads that also lock the file, such
+/// as whisper.py with locking enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockMode {
+    /// Don't lock files at all.
+    None,
+    /// Wait as long as required to acquire a lock.
+    Blocking,
+    /// Return an error right away if a lock can't be acquired.
+    NonBlocking,
+    /// Wait up to the given amount of time to acquire a lock, returning an
+    /// error if it couldn't be acquired in that time.
+    Timeout(Duration),
+}
+
+impl Default for LockMode {
+    fn default() -> LockMode {
+        LockMode::None
+    }
+}
+
+fn is_contended(err: &io::Error) -> bool {
+    err.kind() == fs2::lock_contended_error().kind()
+}
+
+fn lock_with<B, T>(file: &File, mode: LockMode, block: B, try_lock: T) -> MementoResult<()>
+where
+    B: Fn(&File) -> io::Result<()>,
+    T: Fn(&File) -> io::Result<()>,
+{
+    let deadline = match mode {
+        LockMode::None => return Ok(()),
+        LockMode::Blocking => return Ok(block(file)?),
+        LockMode::NonBlocking => Instant::now(),
+        LockMode::Timeout(d) => Instant::now() + d,
+    };
+
+    loop {
+        match try_lock(file) {
+            Ok(_) => return Ok(()),
+            Err(ref e) if is_contended(e) && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(LOCK_RETRY_INTERVAL_MS));
+            }
+            Err(ref e) if is_contended(e) => {
+                return Err(MementoError::from((
+                    ErrorKind::LockUnavailable,
+                    "unable to acquire lock",
+                )));
+            }
+            Err(e) => return Err(MementoError::from(e)),
+        }
+    }
+}
+
+/// Acquire a shared lock for reading the given file based on `mode`.
+///
+/// # Errors
+///
+/// Return an error if the lock could not be acquired in the time allowed by
+/// `mode` or if there was an I/O error acquiring it.
+pub fn lock_shared(file: &File, mode: LockMode) -> MementoResult<()> {
+    // Calls are qualified since `File` has inherent locking methods with the
+    // same names in newer versions of the standard library.
+    lock_with(file, mode, FileExt::lock_shared, FileExt::try_lock_shared)
+}
+
+/// Acquire an exclusive lock for writing the given file based on `mode`.
+///
+/// # Errors
+///
+/// Return an error if the lock could not be acquired in the time allowed by
+/// `mode` or if there was an I/O error acquiring it.
+pub fn lock_exclusive(file: &File, mode: LockMode) -> MementoResult<()> {
+    lock_with(
+        file,
+        mode,
+        FileExt::lock_exclusive,
+        FileExt::try_lock_exclusive,
+    )
+}
+
+/// Release a lock acquired with `lock_shared` or `lock_exclusive` based on
+/// `mode` without closing the file.
+///
+/// # Errors
+///
+/// Return an error if there was an I/O error releasing the lock.
+pub fn unlock(file: &File, mode: LockMode) -> MementoResult<()> {
+    match mode {
+        LockMode::None => Ok(()),
+        _ => Ok(FileExt::unlock(file)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{File, OpenOptions};
+    use std::time::{Duration, Instant};
+
+    use memento_core::errors::ErrorKind;
+    use tempfile::NamedTempFile;
+
+    use super::{lock_exclusive, lock_shared, unlock, LockMode};
+
+    fn open_twice() -> (NamedTempFile, File, File) {
+        let tmp = NamedTempFile::new().unwrap();
+        let first = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(tmp.path())
+            .unwrap();
+        let second = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(tmp.path())
+            .unwrap();
+        (tmp, first, second)
+    }
+
+    #[test]
+    fn test_lock_none_ignores_existing_lock() {
+        let (_tmp, first, second) = open_twice();
+        lock_exclusive(&first, LockMode::Blocking).unwrap();
+
+        assert!(lock_exclusive(&second, LockMode::None).is_ok());
+        assert!(lock_shared(&second, LockMode::None).is_ok());
+    }
+
+    #[test]
+    fn test_lock_shared_with_shared() {
+        let (_tmp, first, second) = open_twice();
+        lock_shared(&first, LockMode::Blocking).unwrap();
+
+        assert!(lock_shared(&second, LockMode::NonBlocking).is_ok());
+    }
+
+    #[test]
+    fn test_lock_shared_with_exclusive() {
+        let (_tmp, first, second) = open_twice();
+        lock_exclusive(&first, LockMode::Blocking).unwrap();
+
+        let err = lock_shared(&second, LockMode::NonBlocking).unwrap_err();
+        assert_eq!(ErrorKind::LockUnavailable, err.kind());
+    }
+
+    #[test]
+    fn test_lock_exclusive_with_shared() {
+        let (_tmp, first, second) = open_twice();
+        lock_shared(&first, LockMode::Blocking).unwrap();
+
+        let err = lock_exclusive(&second, LockMode::NonBlocking).unwrap_err();
+        assert_eq!(ErrorKind::LockUnavailable, err.kind());
+    }
+
+    #[test]
+    fn test_lock_exclusive_timeout() {
+        let (_tmp, first, second) = open_twice();
+        lock_exclusive(&first, LockMode::Blocking).unwrap();
+
+        let start = Instant::now();
+        let timeout = Duration::from_millis(50);
+        let err = lock_exclusive(&second, LockMode::Timeout(timeout)).unwrap_err();
+
+        assert_eq!(ErrorKind::LockUnavailable, err.kind());
+        assert!(start.elapsed() >= timeout);
+    }
+
+    #[test]
+    fn test_lock_released_on_close() {
+        let (_tmp, first, second) = open_twice();
+        lock_exclusive(&first, LockMode::Blocking).unwrap();
+        drop(first);
+
+        assert!(lock_exclusive(&second, LockMode::NonBlocking).is_ok());
+    }
+
+    #[test]
+    fn test_unlock() {
+        let (_tmp, first, second) = open_twice();
+        lock_exclusive(&first, LockMode::Blocking).unwrap();
+        unlock(&first, LockMode::Blocking).unwrap();
+
+        assert!(lock_exclusive(&second, LockMode::NonBlocking).is_ok());
+    }
+}
@@ -11,20 +11,39 @@
 //!
 
 use std::fmt::Debug;
-use std::fs::File;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
 use chrono::{DateTime, Duration, TimeZone, Utc};
 
 use memmap::Mmap;
 
+use check::{check, CheckReport};
+use diff::{diff, DatabaseDiff};
 use io::{SliceReader, SliceReaderDirect, SliceReaderMapped};
+use lock::{lock_shared, LockMode};
 use memento_core::errors::{ErrorKind, MementoError, MementoResult};
 use memento_core::parser::{
-    memento_parse_archive, memento_parse_archive_infos, memento_parse_database,
-    memento_parse_metadata,
+    memento_decode_points, memento_parse_archive_infos, memento_parse_archive_view,
+    memento_parse_database, memento_parse_metadata,
 };
-use memento_core::types::{Archive, ArchiveInfo, Header, MementoDatabase, Metadata, Point};
+use memento_core::types::{ArchiveInfo, Header, MementoDatabase, Metadata, Point};
+use memento_core::view::ArchiveView;
+
+/// How the archive used to fulfill a request is picked.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+enum ArchiveSelection {
+    /// The highest precision archive that covers the range.
+    Retention,
+    /// The archive at the given index.
+    Index(usize),
+    /// The highest precision archive that covers the range with at
+    /// least the given number of seconds per point.
+    MinSecondsPerPoint(u32),
+}
 
 /// Request describing a time range to fetch values for.
 ///
@@ -34,6 +53,7 @@ pub struct FetchRequest {
     from: DateTime<Utc>,
     until: DateTime<Utc>,
     now: DateTime<Utc>,
+    archive: ArchiveSelection,
 }
 
 impl FetchRequest {
@@ -46,6 +66,7 @@ impl FetchRequest {
             from: from.with_timezone(&Utc),
             until: until.with_timezone(&Utc),
             now: now.with_timezone(&Utc),
+            archive: ArchiveSelection::Retention,
         }
     }
 
@@ -79,6 +100,26 @@ impl FetchRequest {
         self
     }
 
+    /// Use the archive at the given index (ordered from highest to lowest
+    /// precision) for this request instead of the highest precision archive
+    /// that covers the requested range.
+    ///
+    /// The request will fail if the archive doesn't cover the range.
+    pub fn with_archive(mut self, index: usize) -> Self {
+        self.archive = ArchiveSelection::Index(index);
+        self
+    }
+
+    /// Use the highest precision archive that covers the requested range
+    /// and has at least the given number of seconds per point for this
+    /// request.
+    ///
+    /// The request will fail if there is no such archive.
+    pub fn with_min_seconds_per_point(mut self, secs: u32) -> Self {
+        self.archive = ArchiveSelection::MinSecondsPerPoint(secs);
+        self
+    }
+
     /// Create a new request coerced to values that make sense or return
     /// an error if there's no way the request could be fulfilled.
     fn normalize(&self, header: &Header) -> MementoResult<Self> {
@@ -116,7 +157,19 @@ impl FetchRequest {
             self.from
         };
 
-        Ok(FetchRequest::new(from, self.until, self.now))
+        // end time is in the future, adjust
+        let until = if self.until > self.now {
+            self.now
+        } else {
+            self.until
+        };
+
+        Ok(FetchRequest {
+            from: from,
+            until: until,
+            now: self.now,
+            archive: self.archive,
+        })
     }
 
     /// Required retention time of a database to fulfill this request.
@@ -136,32 +189,84 @@ impl Default for FetchRequest {
     }
 }
 
+/// Values of a database for a time range, one for each interval of the
+/// archive used to fulfill the request.
 ///
-///
-///
+/// Like whisper.py, the range starts at `from` and ends at `until` (both
+/// rounded to an interval of the archive), exclusive of `until`. Intervals
+/// without a value, or with a value left over from a previous trip around
+/// the archive, are `None`.
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct FetchResponse {
     archive: ArchiveInfo,
+    from: u32,
+    until: u32,
+    values: Vec<Option<f64>>,
     points: Vec<Point>,
 }
 
 impl FetchResponse {
-    pub fn new(archive: ArchiveInfo, points: Vec<Point>) -> FetchResponse {
+    pub fn new(
+        archive: ArchiveInfo,
+        from: u32,
+        until: u32,
+        values: Vec<Option<f64>>,
+    ) -> FetchResponse {
+        let step = archive.seconds_per_point();
+        let points = values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.map(|v| Point::new(from + i as u32 * step, v)))
+            .collect();
+
         FetchResponse {
             archive: archive,
+            from: from,
+            until: until,
+            values: values,
             points: points,
         }
     }
 
+    /// Archive used to fulfill the request.
     pub fn archive(&self) -> &ArchiveInfo {
         &self.archive
     }
 
+    /// Timestamp of the first interval.
+    pub fn from(&self) -> u32 {
+        self.from
+    }
+
+    /// Timestamp of the end of the last interval.
+    pub fn until(&self) -> u32 {
+        self.until
+    }
+
+    /// Number of seconds between intervals.
+    pub fn step(&self) -> u32 {
+        self.archive.seconds_per_point()
+    }
+
+    /// Value of each interval, from oldest to newest.
+    pub fn values(&self) -> &[Option<f64>] {
+        &self.values
+    }
+
+    /// Points for each interval that has a value, from oldest to newest.
     pub fn points(&self) -> &[Point] {
         &self.points
     }
 }
 
+impl Into<((u32, u32, u32), Vec<Option<f64>>)> for FetchResponse {
+    /// Convert to the same `((from, until, step), values)` structure returned
+    /// by `fetch` in whisper.py.
+    fn into(self) -> ((u32, u32, u32), Vec<Option<f64>>) {
+        ((self.from, self.until, self.step()), self.values)
+    }
+}
+
 impl Into<(ArchiveInfo, Vec<Point>)> for FetchResponse {
     fn into(self) -> (ArchiveInfo, Vec<Point>) {
         (self.archive, self.points)
@@ -219,38 +324,143 @@ where
 
     pub fn read_range(&mut self, req: &FetchRequest) -> MementoResult<FetchResponse> {
         let header = self.read_header()?;
+        self.read_range_with_header(&header, req)
+    }
+
+    /// Read a range of values using a header that has already been parsed
+    /// from the same file instead of parsing it again.
+    pub fn read_range_with_header(
+        &mut self,
+        header: &Header,
+        req: &FetchRequest,
+    ) -> MementoResult<FetchResponse> {
         let range = DateRangeSearch::new();
-        range.search(self.reader, &header, req)
+        range.search(self.reader, header, req)
+    }
+
+    /// Pass a view of the points of the archive at the given index to
+    /// `consumer` without decoding or copying all of them up front.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if there is no archive at the given index, if
+    /// the archive could not be read, or if `consumer` returns an error.
+    pub fn view_archive<F, R>(
+        &mut self,
+        header: &Header,
+        index: usize,
+        consumer: F,
+    ) -> MementoResult<R>
+    where
+        F: Fn(ArchiveView) -> MementoResult<R>,
+    {
+        let info = header.archive_info().get(index).ok_or_else(|| {
+            MementoError::from((ErrorKind::NoArchiveAvailable, "no archive available"))
+        })?;
+
+        self.reader
+            .consume(u64::from(info.offset()), info.archive_size(), |v| {
+                consumer(ArchiveView::new(v))
+            })
+            .map_err(|e| e.with_archive(index))
+    }
+
+    /// Pass a view of the points of every archive, in the same order as the
+    /// header, to `consumer` without decoding or copying all of them up front.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if the archives could not be read, if any of
+    /// them extend past the end of the file, or if `consumer` returns an error.
+    pub fn view_archives<F, R>(&mut self, header: &Header, consumer: F) -> MementoResult<R>
+    where
+        F: Fn(&[ArchiveView]) -> MementoResult<R>,
+    {
+        self.reader.consume_all(|v| {
+            let mut views = Vec::with_capacity(header.archive_info().len());
+            for (i, info) in header.archive_info().iter().enumerate() {
+                let start = (info.offset() as usize).min(v.len());
+                let view = memento_parse_archive_view(&v[start..], info)
+                    .to_full_result()
+                    .map_err(|e| MementoError::from(e).with_archive(i))?;
+                views.push(view);
+            }
+
+            consumer(&views)
+        })
     }
 }
 
-fn new_direct_reader<P>(path: P) -> MementoResult<SliceReaderDirect>
-where
-    P: AsRef<Path>,
-{
-    let file = File::open(path)?;
-    Ok(SliceReaderDirect::new(file))
+/// Add the path of every file with a `.wsp` extension in a directory, and
+/// all directories under it, to `paths`.
+fn find_databases(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_databases(&path, paths)?;
+        } else if path.extension() == Some("wsp".as_ref()) {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
 }
 
-fn new_mapped_reader<P>(path: P) -> MementoResult<SliceReaderMapped>
-where
-    P: AsRef<Path>,
-{
-    let file = File::open(path)?;
-    let map = unsafe { Mmap::map(&file)? };
+fn new_mapped_reader(file: &File) -> MementoResult<SliceReaderMapped> {
+    let map = unsafe { Mmap::map(file)? };
     Ok(SliceReaderMapped::new(map))
 }
 
-///
-///
-///
-///
+/// Read whisper database files from disk, optionally acquiring a shared
+/// lock on each file while it is being read.
 #[derive(Debug)]
-pub struct MementoFileReader;
+pub struct MementoFileReader {
+    lock: LockMode,
+    threads: usize,
+}
 
 impl MementoFileReader {
+    /// Create a new reader that doesn't lock files and uses one thread per
+    /// available CPU when reading multiple files.
     pub fn new() -> Self {
-        MementoFileReader
+        MementoFileReader {
+            lock: LockMode::None,
+            threads: thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+        }
+    }
+
+    /// Use the given locking mode when reading files.
+    pub fn with_lock(mut self, val: LockMode) -> Self {
+        self.lock = val;
+        self
+    }
+
+    /// Use (at most) the given number of threads when reading multiple
+    /// files. Values less than one are treated as one.
+    pub fn with_threads(mut self, val: usize) -> Self {
+        self.threads = val;
+        self
+    }
+
+    /// Open a file for reading, lock it based on the locking mode of this
+    /// reader, and pass it to `consumer`. The lock is held until the file
+    /// is closed. Any error is annotated with the path of the file.
+    fn with_file<P, F, T>(&self, path: P, consumer: F) -> MementoResult<T>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(File) -> MementoResult<T>,
+    {
+        let path = path.as_ref();
+        let res = File::open(path)
+            .map_err(MementoError::from)
+            .and_then(|file| {
+                lock_shared(&file, self.lock)?;
+                consumer(file)
+            });
+
+        res.map_err(|e| e.with_path(path))
     }
 
     /// Read only the header of a whisper database file.
@@ -258,15 +468,17 @@ impl MementoFileReader {
     /// # Errors
     ///
     /// Return an error result if there were any I/O errors reading
-    /// the database file (such as permission errors) or if it was
-    /// malformed.
+    /// the database file (such as permission errors), if it was
+    /// malformed, or if it could not be locked.
     pub fn read_header<P>(&self, path: P) -> MementoResult<Header>
     where
         P: AsRef<Path>,
     {
-        let mut reader = new_direct_reader(path)?;
-        let mut parser = MementoParser::new(&mut reader);
-        parser.read_header()
+        self.with_file(path, |file| {
+            let mut reader = SliceReaderDirect::new(file);
+            let mut parser = MementoParser::new(&mut reader);
+            parser.read_header()
+        })
     }
 
     /// Read and entire whisper database file (header + data).
@@ -274,15 +486,18 @@ impl MementoFileReader {
     /// # Errors
     ///
     /// Return an error result if there were any I/O errors reading
-    /// the database file (such as permission errors) or if it was
-    /// malformed.
+    /// the database file (such as permission errors), if it was
+    /// malformed, or if it could not be locked.
     pub fn read_database<P>(&self, path: P) -> MementoResult<MementoDatabase>
     where
         P: AsRef<Path>,
     {
-        let mut reader = new_mapped_reader(path)?;
-        let mut parser = MementoParser::new(&mut reader);
-        parser.read_database()
+        // The file is kept open, and hence locked, until parsing is complete
+        self.with_file(path, |file| {
+            let mut reader = new_mapped_reader(&file)?;
+            let mut parser = MementoParser::new(&mut reader);
+            parser.read_database()
+        })
     }
 
     /// Read a portion of a whisper database file based on the given
@@ -292,15 +507,166 @@ impl MementoFileReader {
     ///
     /// Return an error result if there were any I/O errors reading
     /// the database file (such as permission errors), if the file was
-    /// malformed, or if the request could not be fulfilled by this
-    /// database file.
+    /// malformed or could not be locked, or if the request could not be
+    /// fulfilled by this database file.
     pub fn read<P>(&self, path: P, req: &FetchRequest) -> MementoResult<FetchResponse>
     where
         P: AsRef<Path>,
     {
-        let mut reader = new_mapped_reader(path)?;
-        let mut parser = MementoParser::new(&mut reader);
-        parser.read_range(req)
+        self.with_file(path, |file| {
+            let mut reader = new_mapped_reader(&file)?;
+            let mut parser = MementoParser::new(&mut reader);
+            parser.read_range(req)
+        })
+    }
+
+    /// Call `f` for each of the given items using the configured number of
+    /// threads, returning the results in the same order as the items.
+    fn map_parallel<I, T, F>(&self, items: &[I], f: F) -> Vec<T>
+    where
+        I: Sync,
+        T: Send,
+        F: Fn(&I) -> T + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let workers = self.threads.max(1).min(items.len());
+
+        let mut results: Vec<(usize, T)> = thread::scope(|s| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut out = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            if i >= items.len() {
+                                return out;
+                            }
+
+                            out.push((i, f(&items[i])));
+                        }
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().expect("reader thread panicked"))
+                .collect()
+        });
+
+        results.sort_by_key(|&(i, _)| i);
+        results.into_iter().map(|(_, res)| res).collect()
+    }
+
+    /// Read a portion of each of the given whisper database files based on
+    /// the given request, reading files in parallel.
+    ///
+    /// A result is returned for each path, in the same order as the paths.
+    /// Errors reading one file (see `read`) don't prevent other files from
+    /// being read.
+    pub fn read_many<P>(&self, paths: &[P], req: &FetchRequest) -> Vec<MementoResult<FetchResponse>>
+    where
+        P: AsRef<Path> + Sync,
+    {
+        self.map_parallel(paths, |p| self.read(p, req))
+    }
+
+    /// Check a whisper database file for problems without trusting any part
+    /// of it (see `check::check`).
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if there were any I/O errors reading the
+    /// database file (such as permission errors) or if it could not be
+    /// locked. Problems with the contents of the file are part of the report.
+    pub fn check<P>(&self, path: P) -> MementoResult<CheckReport>
+    where
+        P: AsRef<Path>,
+    {
+        self.with_file(path, |file| {
+            // Empty files can't be mapped but are still checked
+            if file.metadata()?.len() == 0 {
+                return Ok(check(&[]));
+            }
+
+            let map = unsafe { Mmap::map(&file)? };
+            Ok(check(&map))
+        })
+    }
+
+    /// Check each of the given whisper database files for problems (see
+    /// `check`), checking files in parallel.
+    ///
+    /// A result is returned for each path, in the same order as the paths.
+    pub fn check_many<P>(&self, paths: &[P]) -> Vec<MementoResult<CheckReport>>
+    where
+        P: AsRef<Path> + Sync,
+    {
+        self.map_parallel(paths, |p| self.check(p))
+    }
+
+    /// Check every whisper database file (any file with a `.wsp` extension)
+    /// in a directory and all directories under it for problems (see
+    /// `check`), checking files in parallel.
+    ///
+    /// The path of each file is returned along with the result of checking
+    /// it, ordered by path.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if the directory, or any directory under it,
+    /// could not be read.
+    pub fn check_tree<P>(&self, dir: P) -> MementoResult<Vec<(PathBuf, MementoResult<CheckReport>)>>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        let mut paths = Vec::new();
+        find_databases(dir, &mut paths).map_err(|e| MementoError::from(e).with_path(dir))?;
+        paths.sort();
+
+        let results = self.check_many(&paths);
+        Ok(paths.into_iter().zip(results).collect())
+    }
+
+    /// Pass the header and a view of the points of every archive of a whisper
+    /// database file to `consumer`, without copying the file or decoding all
+    /// of the points up front (see `MementoParser::view_archives`).
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if there were any I/O errors reading the
+    /// database file (such as permission errors), if it was malformed or
+    /// could not be locked, or if `consumer` returns an error.
+    pub fn view_archives<P, F, R>(&self, path: P, consumer: F) -> MementoResult<R>
+    where
+        P: AsRef<Path>,
+        F: Fn(&Header, &[ArchiveView]) -> MementoResult<R>,
+    {
+        self.with_file(path, |file| {
+            let mut reader = new_mapped_reader(&file)?;
+            let mut parser = MementoParser::new(&mut reader);
+            let header = parser.read_header()?;
+            parser.view_archives(&header, |views| consumer(&header, views))
+        })
+    }
+
+    /// Read two entire whisper database files and compare them archive
+    /// by archive (see `diff::diff`).
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if there were any I/O errors reading either
+    /// of the database files (such as permission errors), or if either was
+    /// malformed or could not be locked.
+    pub fn diff<P, Q>(&self, first: P, second: Q) -> MementoResult<DatabaseDiff>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let first = self.read_database(first)?;
+        let second = self.read_database(second)?;
+        Ok(diff(&first, &second))
     }
 }
 
@@ -316,37 +682,127 @@ impl DateRangeSearch {
     }
 
     /// Find the archive in this file that is capable of fulfilling the
-    /// given request or return an error if there is no archive that can
+    /// given request, along with its index, or return an error if there
+    /// is no archive that can
     fn find_archive<'b, 'c>(
         req: &'b FetchRequest,
         header: &'c Header,
-    ) -> MementoResult<&'c ArchiveInfo> {
-        let archives = header.archive_info();
+    ) -> MementoResult<(usize, &'c ArchiveInfo)> {
+        let mut archives = header.archive_info().iter().enumerate();
         let required_retention = req.retention();
-
-        for archive in archives {
-            let retention = Duration::seconds(i64::from(archive.retention()));
-            if retention >= required_retention {
-                return Ok(archive);
+        let covers =
+            |a: &ArchiveInfo| Duration::seconds(a.retention() as i64) >= required_retention;
+
+        let found = match req.archive {
+            ArchiveSelection::Retention => archives.find(|&(_, a)| covers(a)),
+            ArchiveSelection::Index(i) => archives.nth(i).filter(|&(_, a)| covers(a)),
+            ArchiveSelection::MinSecondsPerPoint(secs) => {
+                archives.find(|&(_, a)| a.seconds_per_point() >= secs && covers(a))
             }
+        };
+
+        found.ok_or_else(|| {
+            MementoError::from((ErrorKind::NoArchiveAvailable, "no archive available"))
+        })
+    }
+
+    /// Get the start and end of the range of intervals of the given archive
+    /// required for the given request.
+    ///
+    /// The range is computed in the same way as whisper.py: `from` and `until`
+    /// are both moved to the start of the next interval and the range always
+    /// includes at least one interval.
+    fn intervals_for_request(info: &ArchiveInfo, request: &FetchRequest) -> (i64, i64) {
+        let step = i64::from(info.seconds_per_point());
+        let from = request.from.timestamp();
+        let until = request.until.timestamp();
+
+        let from_interval = from - from.rem_euclid(step) + step;
+        let mut until_interval = until - until.rem_euclid(step) + step;
+        if from_interval == until_interval {
+            until_interval += step;
         }
 
-        Err(MementoError::from((
-            ErrorKind::NoArchiveAvailable,
-            "no archive available",
-        )))
+        (from_interval, until_interval)
     }
 
-    /// Get the subset of points in the given archive required for the
-    /// given request.
-    fn points_for_request(archive: &Archive, request: &FetchRequest) -> Vec<Point> {
-        archive
-            .points()
-            .iter()
-            .filter(|p| Utc.timestamp(i64::from(p.timestamp()), 0) >= request.from)
-            .filter(|p| Utc.timestamp(i64::from(p.timestamp()), 0) <= request.until)
-            .cloned()
-            .collect()
+    /// Read `count` consecutive points of the given archive starting at the
+    /// absolute offset `start`, wrapping around to the beginning of the archive
+    /// if the end of it is reached. Only the bytes for the points are read.
+    fn read_points<T>(
+        reader: &mut T,
+        info: &ArchiveInfo,
+        start: u64,
+        count: u64,
+    ) -> MementoResult<Vec<Point>>
+    where
+        T: SliceReader,
+    {
+        let archive_end = u64::from(info.offset()) + info.archive_size();
+        let first = count.min((archive_end - start) / Point::storage());
+        let second = count - first;
+
+        let mut points = reader.consume(start, first * Point::storage(), |v| {
+            Ok(memento_decode_points(v, first as usize).to_full_result()?)
+        })?;
+
+        if second > 0 {
+            let rest =
+                reader.consume(u64::from(info.offset()), second * Point::storage(), |v| {
+                    Ok(memento_decode_points(v, second as usize).to_full_result()?)
+                })?;
+            points.extend(rest);
+        }
+
+        Ok(points)
+    }
+
+    /// Get the value of each interval of the given archive between the
+    /// `from` and `until` intervals, reading only the points required.
+    ///
+    /// The slot for each interval is found relative to the first point in the
+    /// archive (the "base" point) and only points with the expected timestamp
+    /// for an interval are used.
+    fn read_values<T>(
+        reader: &mut T,
+        info: &ArchiveInfo,
+        from: i64,
+        until: i64,
+    ) -> MementoResult<Vec<Option<f64>>>
+    where
+        T: SliceReader,
+    {
+        let step = i64::from(info.seconds_per_point());
+        let count = ((until - from) / step) as usize;
+        let offset = u64::from(info.offset());
+        let base = Self::read_points(reader, info, offset, 1)?[0].timestamp();
+
+        // An archive that has never been written to has no values
+        if base == 0 {
+            return Ok(vec![None; count]);
+        }
+
+        // Points are read starting from the slot for the `from` interval. A
+        // range longer than the archive just wraps around to the same slots.
+        let slot = (from - i64::from(base))
+            .div_euclid(step)
+            .rem_euclid(i64::from(info.num_points()));
+        let start = offset + slot as u64 * Point::storage();
+        let len = (count as u64).min(u64::from(info.num_points()));
+        let series = Self::read_points(reader, info, start, len)?;
+
+        let values = (0..count)
+            .map(|i| {
+                let point = &series[i % series.len()];
+                if i64::from(point.timestamp()) == from + i as i64 * step {
+                    Some(point.value())
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        Ok(values)
     }
 
     fn search<T>(
@@ -363,48 +819,50 @@ impl DateRangeSearch {
         // example: bump up the starting range to our earliest range if
         // that's the only thing preventing us from handling this request.
         let req = req.normalize(&header)?;
-        let archive_info = Self::find_archive(&req, &header)?;
-        // Get the section of the mmaped file that we should be looking
-        // at based on the archive that can actually be used to satisfy
-        // the requested ranges.
-        let archive_offset = archive_info.offset() as u64;
-        let archive_len = archive_info.archive_size() as u64;
-        let archive = reader
-            .consume(archive_offset, archive_len, |v| {
-                Ok(memento_parse_archive(v, archive_info).to_full_result()?)
-            })
-            .map_err(|e| {
-                // The reader returns an I/O error for invalid seeks or out
-                // of bounds reads. Telling people that we expected X bytes
-                // and got Y bytes isn't super useful so we translate into
-                // something a little nicer here: corrupt DB.
-                match e.kind() {
-                    ErrorKind::IoError => MementoError::from((
-                        ErrorKind::CorruptDatabase,
-                        "I/O error reading archive",
-                    )),
-                    _ => e,
+        let (index, archive_info) = Self::find_archive(&req, &header)?;
+        let (from, until) = Self::intervals_for_request(archive_info, &req);
+
+        // Read only the section of the file for the requested range from
+        // the archive that can actually be used to satisfy it.
+        let values = Self::read_values(reader, archive_info, from, until).map_err(|e| {
+            // The reader returns an I/O error for invalid seeks or out
+            // of bounds reads. Telling people that we expected X bytes
+            // and got Y bytes isn't super useful on its own so we translate
+            // into something a little nicer here: corrupt DB. The original
+            // error is kept as the cause.
+            let e = match e.kind() {
+                ErrorKind::IoError => {
+                    MementoError::from((ErrorKind::CorruptDatabase, "I/O error reading archive", e))
                 }
-            })?;
+                _ => e,
+            };
 
-        let points = Self::points_for_request(&archive, &req);
-        // Include a copy of the archive info along with the points returned
-        // so that consumers can tell the resolution of the data without
-        // having to inspect the points.
-        Ok(FetchResponse::new(archive_info.clone(), points))
+            e.with_archive(index)
+        })?;
+
+        // Include a copy of the archive info along with the values returned
+        // so that consumers can tell the resolution of the data.
+        Ok(FetchResponse::new(
+            archive_info.clone(),
+            from as u32,
+            until as u32,
+            values,
+        ))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use chrono::{DateTime, Utc};
+    use std::error::Error;
+
+    use chrono::{DateTime, TimeZone, Utc};
 
     use memento_core::encoder::{memento_encode_archive, memento_encode_header};
-    use memento_core::errors::ErrorKind;
+    use memento_core::errors::{ErrorKind, MementoResult};
     use memento_core::types::{AggregationType, Archive, ArchiveInfo, Header, Metadata, Point};
 
-    use super::{DateRangeSearch, FetchRequest};
-    use io::SliceReaderMapped;
+    use super::{DateRangeSearch, FetchRequest, FetchResponse, MementoParser};
+    use io::{SliceReader, SliceReaderMapped};
 
     fn get_file_header() -> Header {
         let metadata = Metadata::new(
@@ -428,7 +886,7 @@ mod tests {
     }
 
     fn get_archive(info: &ArchiveInfo, now: DateTime<Utc>) -> Archive {
-        let start_secs = now.timestamp() as u32 - info.retention();
+        let start_secs = now.timestamp() as u32 - info.retention() as u32;
 
         let vals = (0..info.num_points())
             .map(|i| start_secs + (i * info.seconds_per_point()))
@@ -526,7 +984,14 @@ mod tests {
         let res = range.search(&mut reader, &header, &req);
 
         assert!(res.is_err());
-        assert_eq!(ErrorKind::CorruptDatabase, res.unwrap_err().kind());
+
+        // The original I/O error is kept as the cause along with where the
+        // read that failed was.
+        let err = res.unwrap_err();
+        assert_eq!(ErrorKind::CorruptDatabase, err.kind());
+        assert_eq!(Some(0), err.archive());
+        assert!(err.offset().unwrap() >= 80);
+        assert!(err.source().is_some());
     }
 
     #[test]
@@ -558,8 +1023,9 @@ mod tests {
         let range = DateRangeSearch::new();
         let res = range.search(&mut reader, &header, &req);
 
-        assert!(res.is_ok());
-        assert!(res.unwrap().points().is_empty());
+        let response = res.unwrap();
+        assert_eq!(360, response.values().len());
+        assert!(response.values().iter().all(|v| v.is_none()));
     }
 
     #[test]
@@ -591,8 +1057,9 @@ mod tests {
         let range = DateRangeSearch::new();
         let res = range.search(&mut reader, &header, &req);
 
-        assert!(res.is_ok());
-        assert!(res.unwrap().points().is_empty());
+        let response = res.unwrap();
+        assert_eq!(360, response.values().len());
+        assert!(response.values().iter().all(|v| v.is_none()));
     }
 
     #[test]
@@ -626,15 +1093,21 @@ mod tests {
 
         assert!(res.is_ok());
 
+        // The range starts and ends at the interval after the requested
+        // times and includes 361 intervals, only the last two have values.
         let response = res.unwrap();
-        assert_eq!(2, response.points().len());
+        assert_eq!(from.timestamp() as u32 + 60, response.from());
+        assert_eq!(until.timestamp() as u32 + 60, response.until());
+        assert_eq!(60, response.step());
+        assert_eq!(361, response.values().len());
         assert_eq!(
-            &vec![
+            vec![
                 Point::new(until.timestamp() as u32 - 60, 7.0),
                 Point::new(until.timestamp() as u32, 7.0),
-            ] as &[Point],
+            ],
             response.points()
         );
+        assert_eq!(&[Some(7.0), Some(7.0)], &response.values()[359..]);
     }
 
     #[test]
@@ -669,16 +1142,246 @@ mod tests {
         assert!(res.is_ok());
 
         let response = res.unwrap();
-        assert_eq!(2, response.points().len());
+        assert_eq!(from.timestamp() as u32 + 300, response.from());
+        assert_eq!(until.timestamp() as u32 + 300, response.until());
+        assert_eq!(300, response.step());
+        assert_eq!(73, response.values().len());
         assert_eq!(
-            &vec![
+            vec![
                 Point::new(until.timestamp() as u32 - 300, 7.0),
                 Point::new(until.timestamp() as u32, 7.0),
-            ] as &[Point],
+            ],
             response.points()
         );
     }
 
+    fn search_file_header(req: &FetchRequest) -> MementoResult<FetchResponse> {
+        let header = get_file_header();
+        let start = parse_utc("1997-08-27T18:00:00+0000");
+        let archive1 = get_archive(&header.archive_info()[0], start);
+        let archive2 = get_archive(&header.archive_info()[1], start);
+
+        let mut buf = vec![];
+        memento_encode_header(&mut buf, &header).unwrap();
+        memento_encode_archive(&mut buf, &archive1).unwrap();
+        memento_encode_archive(&mut buf, &archive2).unwrap();
+
+        let mut reader = SliceReaderMapped::new(buf);
+        let range = DateRangeSearch::new();
+        range.search(&mut reader, &header, req)
+    }
+
+    #[test]
+    fn test_read_with_archive() {
+        let req = FetchRequest::default()
+            .with_now(parse_utc("1997-08-27T02:14:00+0000"))
+            .with_from(parse_utc("1997-08-26T12:00:00+0000"))
+            .with_until(parse_utc("1997-08-26T18:01:00+0000"));
+
+        let response = search_file_header(&req.clone().with_archive(0)).unwrap();
+        assert_eq!(60, response.step());
+
+        // The range is covered by both archives, use the lower resolution one
+        let response = search_file_header(&req.clone().with_archive(1)).unwrap();
+        assert_eq!(300, response.step());
+        assert_eq!(72, response.values().len());
+
+        let res = search_file_header(&req.with_archive(2));
+        assert_eq!(ErrorKind::NoArchiveAvailable, res.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_read_with_archive_insufficient_retention() {
+        let req = FetchRequest::default()
+            .with_now(parse_utc("1997-08-27T02:14:00+0000"))
+            .with_from(parse_utc("1997-08-20T12:00:00+0000"))
+            .with_until(parse_utc("1997-08-20T18:05:00+0000"))
+            .with_archive(0);
+
+        let res = search_file_header(&req);
+        assert_eq!(ErrorKind::NoArchiveAvailable, res.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_read_with_min_seconds_per_point() {
+        let req = FetchRequest::default()
+            .with_now(parse_utc("1997-08-27T02:14:00+0000"))
+            .with_from(parse_utc("1997-08-26T12:00:00+0000"))
+            .with_until(parse_utc("1997-08-26T18:01:00+0000"));
+
+        let response = search_file_header(&req.clone().with_min_seconds_per_point(60)).unwrap();
+        assert_eq!(60, response.step());
+
+        let response = search_file_header(&req.clone().with_min_seconds_per_point(61)).unwrap();
+        assert_eq!(300, response.step());
+
+        let res = search_file_header(&req.with_min_seconds_per_point(301));
+        assert_eq!(ErrorKind::NoArchiveAvailable, res.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_view_archive() {
+        let header = get_file_header();
+        let start = parse_utc("1997-08-27T18:00:00+0000");
+        let archive1 = get_archive(&header.archive_info()[0], start);
+        let archive2 = get_archive(&header.archive_info()[1], start);
+
+        let mut buf = vec![];
+        memento_encode_header(&mut buf, &header).unwrap();
+        memento_encode_archive(&mut buf, &archive1).unwrap();
+        memento_encode_archive(&mut buf, &archive2).unwrap();
+
+        let mut reader = SliceReaderMapped::new(buf);
+        let mut parser = MementoParser::new(&mut reader);
+
+        let view = parser.view_archive(&header, 1, |v| Ok(v.to_archive()));
+        assert_eq!(archive2, view.unwrap());
+
+        let sum = parser.view_archive(&header, 0, |v| Ok(v.iter().map(|p| p.value()).sum::<f64>()));
+        assert_eq!(7.0 * 1440.0, sum.unwrap());
+
+        let res = parser.view_archive(&header, 2, |v| Ok(v.len()));
+        assert_eq!(ErrorKind::NoArchiveAvailable, res.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_view_archives_truncated() {
+        let header = get_file_header();
+        let archive1 = get_archive(&header.archive_info()[0], Utc::now());
+
+        let mut buf = vec![];
+        memento_encode_header(&mut buf, &header).unwrap();
+        memento_encode_archive(&mut buf, &archive1).unwrap();
+
+        let mut reader = SliceReaderMapped::new(buf);
+        let mut parser = MementoParser::new(&mut reader);
+        let res = parser.view_archives(&header, |v| Ok(v.len()));
+
+        assert_eq!(ErrorKind::ParseError, res.unwrap_err().kind());
+    }
+
+    /// Reader that keeps track of the offset and length of each read.
+    #[derive(Debug)]
+    struct RecordingReader {
+        inner: SliceReaderMapped,
+        reads: Vec<(u64, u64)>,
+    }
+
+    impl SliceReader for RecordingReader {
+        fn consume_all<F, T>(&mut self, consumer: F) -> MementoResult<T>
+        where
+            F: Fn(&[u8]) -> MementoResult<T>,
+        {
+            self.inner.consume_all(consumer)
+        }
+
+        fn consume_from<F, T>(&mut self, offset: u64, consumer: F) -> MementoResult<T>
+        where
+            F: Fn(&[u8]) -> MementoResult<T>,
+        {
+            self.inner.consume_from(offset, consumer)
+        }
+
+        fn consume<F, T>(&mut self, offset: u64, len: u64, consumer: F) -> MementoResult<T>
+        where
+            F: Fn(&[u8]) -> MementoResult<T>,
+        {
+            self.reads.push((offset, len));
+            self.inner.consume(offset, len, consumer)
+        }
+    }
+
+    fn get_small_database(points: Vec<Point>) -> (Header, RecordingReader) {
+        let metadata = Metadata::new(AggregationType::Average, 300, 0.5, 1);
+        let header = Header::new(metadata, vec![ArchiveInfo::new(28, 60, 5)]);
+
+        let mut buf = vec![];
+        memento_encode_header(&mut buf, &header).unwrap();
+        memento_encode_archive(&mut buf, &Archive::new(points)).unwrap();
+
+        let reader = RecordingReader {
+            inner: SliceReaderMapped::new(buf),
+            reads: vec![],
+        };
+
+        (header, reader)
+    }
+
+    #[test]
+    fn test_read_values_wrapped_and_stale() {
+        let base = 1500000000;
+        let (header, mut reader) = get_small_database(vec![
+            Point::new(base, 1.0),
+            Point::new(base + 60, 2.0),
+            // Left over from a previous trip around the archive
+            Point::new(base - 480, 3.0),
+            Point::new(base - 120, 4.0),
+            Point::new(base - 60, 5.0),
+        ]);
+
+        let req = FetchRequest::default()
+            .with_now(Utc.timestamp(i64::from(base) + 100, 0))
+            .with_from(Utc.timestamp(i64::from(base) - 181, 0))
+            .with_until(Utc.timestamp(i64::from(base) + 61, 0));
+
+        let range = DateRangeSearch::new();
+        let response = range.search(&mut reader, &header, &req).unwrap();
+
+        assert_eq!(base - 180, response.from());
+        assert_eq!(base + 120, response.until());
+        assert_eq!(
+            &[None, Some(4.0), Some(5.0), Some(1.0), Some(2.0)],
+            response.values()
+        );
+
+        // The base point, then the end of the archive starting from the slot
+        // for the first interval, then the start of the archive.
+        assert_eq!(vec![(28, 12), (52, 36), (28, 24)], reader.reads);
+    }
+
+    #[test]
+    fn test_read_values_short_range() {
+        let base = 1500000000;
+        let (header, mut reader) = get_small_database(vec![
+            Point::new(base, 1.0),
+            Point::new(base + 60, 2.0),
+            Point::new(base + 120, 3.0),
+            Point::new(base - 120, 4.0),
+            Point::new(base - 60, 5.0),
+        ]);
+
+        let req = FetchRequest::default()
+            .with_now(Utc.timestamp(i64::from(base) + 150, 0))
+            .with_from(Utc.timestamp(i64::from(base) + 30, 0))
+            .with_until(Utc.timestamp(i64::from(base) + 90, 0));
+
+        let range = DateRangeSearch::new();
+        let response = range.search(&mut reader, &header, &req).unwrap();
+
+        assert_eq!(&[Some(2.0)], response.values());
+        assert_eq!(vec![(28, 12), (40, 12)], reader.reads);
+    }
+
+    #[test]
+    fn test_read_values_empty_archive() {
+        let (header, mut reader) = get_small_database(vec![Point::default(); 5]);
+        let req = FetchRequest::default()
+            .with_now(Utc.timestamp(1500000100, 0))
+            .with_from(Utc.timestamp(1500000000, 0))
+            .with_until(Utc.timestamp(1500000030, 0));
+
+        let range = DateRangeSearch::new();
+        let response = range.search(&mut reader, &header, &req).unwrap();
+
+        // Same from and until interval still results in a single interval
+        assert_eq!(1500000060, response.from());
+        assert_eq!(1500000120, response.until());
+        assert_eq!(&[None], response.values());
+
+        // Only the base point needs to be read to know there are no values
+        assert_eq!(vec![(28, 12)], reader.reads);
+    }
+
     #[test]
     fn test_fetch_request_normalize_nonsense_request() {}
 
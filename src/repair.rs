@@ -0,0 +1,599 @@
+// Memento - A Whisper implementation in Rust
+//
+// Copyright 2017-2018 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Functions to repair truncated or partially corrupt Whisper databases
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::Utc;
+
+use memento_core::encoder::memento_encode_point;
+use memento_core::errors::{ErrorKind, MementoError, MementoResult};
+use memento_core::parser::memento_parse_header;
+use memento_core::types::{ArchiveInfo, Header, Point};
+use memento_core::view::ArchiveView;
+
+// Largest number of bytes a file is extended by. A header that requires a
+// file much larger than the one it's in is more likely to be corrupt than
+// the file is to be truncated, and the contents are extended in memory.
+const MAX_EXTENSION: u64 = 256 * 1024 * 1024;
+
+/// A change made to a database while repairing it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Repair {
+    /// The file was extended with zeros to the size required by the header.
+    Extended { from: u64, to: u64 },
+    /// Bytes past the size required by the header were removed.
+    Truncated { from: u64, to: u64 },
+    /// The slot at the given index had a timestamp that was impossible for
+    /// its position and was set to zero. The point is what the slot held.
+    ClearedPoint {
+        archive: usize,
+        index: usize,
+        point: Point,
+    },
+    /// The first slot, which readers use to locate every other slot, didn't
+    /// agree with the rest of the archive. It was set to a marker with the
+    /// given timestamp that does (see `is_base_marker`), keeping the other
+    /// points readable. The point is what the slot held.
+    RewroteBase {
+        archive: usize,
+        point: Point,
+        timestamp: u32,
+    },
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Repair::Extended { from, to } => {
+                write!(f, "extended file from {} to {} bytes", from, to)
+            }
+            Repair::Truncated { from, to } => {
+                write!(f, "truncated file from {} to {} bytes", from, to)
+            }
+            Repair::ClearedPoint {
+                archive,
+                index,
+                ref point,
+            } => write!(
+                f,
+                "archive {} point {}: cleared timestamp {} value {}",
+                archive,
+                index,
+                point.timestamp(),
+                point.value()
+            ),
+            Repair::RewroteBase {
+                archive,
+                ref point,
+                timestamp,
+            } => write!(
+                f,
+                "archive {} point 0: replaced timestamp {} value {} with base marker {}",
+                archive,
+                point.timestamp(),
+                point.value(),
+                timestamp
+            ),
+        }
+    }
+}
+
+/// All changes made to a database while repairing it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RepairReport {
+    repairs: Vec<Repair>,
+}
+
+impl RepairReport {
+    pub fn new(repairs: Vec<Repair>) -> Self {
+        RepairReport { repairs: repairs }
+    }
+
+    /// Changes made, any change to the size of the file first and then
+    /// changes to points ordered by archive and then by position in the archive.
+    pub fn repairs(&self) -> &[Repair] {
+        &self.repairs
+    }
+
+    /// Return true if the database didn't need any changes.
+    pub fn is_unchanged(&self) -> bool {
+        self.repairs.is_empty()
+    }
+}
+
+/// Parse the header of a database and make sure it can be trusted to
+/// repair the rest of the file: the archives must be a valid configuration
+/// and be laid out one after the other, immediately after the header.
+fn read_header(bytes: &[u8]) -> MementoResult<Header> {
+    let header = memento_parse_header(bytes).to_full_result().map_err(|e| {
+        MementoError::from((
+            ErrorKind::CorruptDatabase,
+            "unable to parse header",
+            MementoError::from(e),
+        ))
+    })?;
+
+    header.validate()?;
+
+    let mut expected = header.size();
+    for (i, info) in header.archive_info().iter().enumerate() {
+        if u64::from(info.offset()) != expected {
+            return Err(MementoError::from((
+                ErrorKind::CorruptDatabase,
+                "archive offsets don't match the layout of the header",
+                format!("archive{} ({}), expected {}", i, info.offset(), expected),
+            )));
+        }
+
+        expected += info.archive_size();
+    }
+
+    Ok(header)
+}
+
+/// Return true if a point is a marker written to the first slot of an
+/// archive by `repair` so that readers can locate the other slots.
+///
+/// The marker has a timestamp one second past an interval. Readers round it
+/// up to the next interval when locating slots (just like whisper.py), but
+/// it never matches the timestamp expected for an interval so its value is
+/// never read. Archives with a precision of one second can't have a marker.
+pub fn is_base_marker(info: &ArchiveInfo, index: usize, timestamp: u32) -> bool {
+    let step = info.seconds_per_point();
+    index == 0 && step > 1 && timestamp % step == 1
+}
+
+/// Get the timestamp of the first slot of an archive implied by a point
+/// stored at `index`, reduced to a single cycle of the archive so that points
+/// written in different cycles of a consistent archive all agree.
+fn base_class(info: &ArchiveInfo, index: usize, timestamp: u32) -> i64 {
+    let step = i64::from(info.seconds_per_point());
+    let cycle = step * i64::from(info.num_points());
+    let interval = if is_base_marker(info, index, timestamp) {
+        i64::from(timestamp) + step - 1
+    } else {
+        i64::from(timestamp)
+    };
+
+    (interval - index as i64 * step).rem_euclid(cycle)
+}
+
+/// Zero every slot of an archive that has a timestamp that couldn't have
+/// been written there, recording each one that was cleared.
+///
+/// A timestamp is impossible if it isn't aligned to the precision of the
+/// archive, is in the future, or belongs in a different slot than the rest
+/// of the archive. The base the slots are compared against is the one that
+/// the most plausible points agree on (preferring the first slot on ties),
+/// so a single corrupt slot can't take the rest of the archive with it. If
+/// the first slot doesn't agree with that base, it's replaced by a marker
+/// that does (see `is_base_marker`) instead of being cleared, since readers
+/// treat an archive with an empty first slot as empty.
+fn repair_points(
+    archive: usize,
+    info: &ArchiveInfo,
+    bytes: &mut [u8],
+    now: u32,
+    repairs: &mut Vec<Repair>,
+) {
+    let step = info.seconds_per_point();
+    let plausible = |timestamp: u32| timestamp.is_multiple_of(step) && timestamp <= now;
+
+    let (mut cleared, rebase) = {
+        let view = ArchiveView::new(bytes);
+        let first = view.get(0).unwrap_or_default();
+        let written: Vec<(usize, Point)> = view
+            .iter()
+            .enumerate()
+            .filter(|(_, point)| point.timestamp() != 0)
+            .collect();
+
+        let mut votes = BTreeMap::new();
+        for &(index, ref point) in &written {
+            if plausible(point.timestamp()) {
+                *votes
+                    .entry(base_class(info, index, point.timestamp()))
+                    .or_insert(0) += 1;
+            }
+        }
+
+        let current = if first.timestamp() != 0
+            && (plausible(first.timestamp()) || is_base_marker(info, 0, first.timestamp()))
+        {
+            Some(base_class(info, 0, first.timestamp()))
+        } else {
+            None
+        };
+        let base = votes
+            .iter()
+            .max_by_key(|&(&class, &count)| (count, Some(class) == current))
+            .map(|(&class, _)| class);
+
+        let (kept, cleared): (Vec<_>, Vec<_>) =
+            written.into_iter().partition(|&(index, ref point)| {
+                let timestamp = point.timestamp();
+                (plausible(timestamp) || is_base_marker(info, index, timestamp))
+                    && Some(base_class(info, index, timestamp)) == base
+            });
+
+        // The marker is placed one second past the interval before the one
+        // the first slot would hold alongside the newest point.
+        let rebase = if base.is_some() && base != current && step > 1 {
+            kept.iter()
+                .max_by_key(|&(_, point)| point.timestamp())
+                .and_then(|&(index, ref point)| {
+                    point
+                        .timestamp()
+                        .checked_sub(index as u32 * step)
+                        .and_then(|ts| ts.checked_sub(step - 1))
+                })
+                .filter(|&ts| ts != 0)
+                .map(|ts| (first, ts))
+        } else {
+            None
+        };
+
+        (cleared, rebase)
+    };
+
+    let size = Point::storage() as usize;
+    if let Some((point, timestamp)) = rebase {
+        cleared.retain(|&(index, _)| index != 0);
+        memento_encode_point(&mut &mut bytes[..size], &Point::new(timestamp, 0.0))
+            .expect("slot is large enough for a point");
+
+        repairs.push(Repair::RewroteBase {
+            archive: archive,
+            point: point,
+            timestamp: timestamp,
+        });
+    }
+
+    for (index, point) in cleared {
+        for byte in &mut bytes[index * size..(index + 1) * size] {
+            *byte = 0;
+        }
+
+        repairs.push(Repair::ClearedPoint {
+            archive: archive,
+            index: index,
+            point: point,
+        });
+    }
+}
+
+/// Repair the contents of a database as of `now`, see `repair`.
+fn repair_at(bytes: &mut Vec<u8>, now: u32) -> MementoResult<RepairReport> {
+    let header = read_header(bytes)?;
+    let mut repairs = Vec::new();
+
+    let actual = bytes.len() as u64;
+    let expected = header.file_size();
+    if expected > actual + MAX_EXTENSION {
+        return Err(MementoError::from((
+            ErrorKind::CorruptDatabase,
+            "header requires a file far larger than the existing one",
+            format!("expected {} bytes, found {}", expected, actual),
+        )));
+    }
+
+    if actual < expected {
+        repairs.push(Repair::Extended {
+            from: actual,
+            to: expected,
+        });
+    } else if actual > expected {
+        repairs.push(Repair::Truncated {
+            from: actual,
+            to: expected,
+        });
+    }
+
+    bytes.resize(expected as usize, 0);
+
+    for (i, info) in header.archive_info().iter().enumerate() {
+        let start = info.offset() as usize;
+        let end = start + info.archive_size() as usize;
+        repair_points(i, info, &mut bytes[start..end], now, &mut repairs);
+    }
+
+    Ok(RepairReport::new(repairs))
+}
+
+/// Repair the raw contents of a database in place, returning every change
+/// that was made.
+///
+/// The header is trusted and used to extend (with zeros) or truncate the
+/// contents to the size it requires. Slots of each archive with a timestamp
+/// that is impossible for their position (see `check::check`) are set to
+/// zero, except for the first slot which is rewritten to agree with the rest
+/// of the archive (see `Repair::RewroteBase`). Values are not checked, so
+/// points with a NaN value are kept.
+///
+/// # Errors
+///
+/// Return an error result if the header could not be parsed, if the archives
+/// are not a valid configuration for a database (see `Header::validate`), if
+/// the archives are not laid out one after the other, or if the header
+/// requires extending the contents by more than 256 MiB. The contents are
+/// left unchanged on error.
+pub fn repair(bytes: &mut Vec<u8>) -> MementoResult<RepairReport> {
+    repair_at(bytes, Utc::now().timestamp() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use memento_core::encoder::{memento_encode_archive, memento_encode_header};
+    use memento_core::errors::ErrorKind;
+    use memento_core::types::{AggregationType, Archive, ArchiveInfo, HeaderBuilder, Point};
+
+    use super::{repair_at, Repair};
+
+    fn get_database(points: Vec<Point>) -> Vec<u8> {
+        let header = HeaderBuilder::new()
+            .with_aggregation(AggregationType::Max)
+            .with_archives(vec![
+                ArchiveInfo::new(0, 60, 5),
+                ArchiveInfo::new(0, 300, 2),
+            ])
+            .build();
+
+        let mut buf = vec![];
+        memento_encode_header(&mut buf, &header).unwrap();
+        memento_encode_archive(&mut buf, &Archive::new(points)).unwrap();
+        memento_encode_archive(&mut buf, &Archive::new(vec![Point::default(); 2])).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_repair_unchanged() {
+        let now = 1500000000;
+        let mut bytes = get_database(vec![
+            Point::new(now - 120, 1.0),
+            Point::new(now - 60, 2.0),
+            Point::default(),
+            Point::default(),
+            Point::new(now - 180, 3.0),
+        ]);
+        let original = bytes.clone();
+
+        assert!(repair_at(&mut bytes, now).unwrap().is_unchanged());
+        assert_eq!(original, bytes);
+    }
+
+    #[test]
+    fn test_repair_points() {
+        let now = 1500000000;
+        let mut bytes = get_database(vec![
+            Point::new(now - 120, 1.0),
+            Point::new(now - 59, 2.0),
+            Point::new(now + 60, 3.0),
+            Point::new(now - 600, 4.0),
+            Point::new(now + 60, 5.0),
+        ]);
+        let expected = get_database(vec![
+            Point::new(now - 120, 1.0),
+            Point::default(),
+            Point::default(),
+            Point::default(),
+            Point::default(),
+        ]);
+
+        let report = repair_at(&mut bytes, now).unwrap();
+        assert_eq!(
+            &[
+                Repair::ClearedPoint {
+                    archive: 0,
+                    index: 1,
+                    point: Point::new(now - 59, 2.0),
+                },
+                Repair::ClearedPoint {
+                    archive: 0,
+                    index: 2,
+                    point: Point::new(now + 60, 3.0),
+                },
+                Repair::ClearedPoint {
+                    archive: 0,
+                    index: 3,
+                    point: Point::new(now - 600, 4.0),
+                },
+                Repair::ClearedPoint {
+                    archive: 0,
+                    index: 4,
+                    point: Point::new(now + 60, 5.0),
+                },
+            ],
+            report.repairs()
+        );
+        assert_eq!(expected, bytes);
+    }
+
+    #[test]
+    fn test_repair_points_corrupt_base() {
+        let now = 1500000000;
+        let mut bytes = get_database(vec![
+            Point::new(now + 60, 1.0),
+            Point::new(now - 180, 2.0),
+            Point::new(now - 120, 3.0),
+            Point::new(now - 600, 4.0),
+            Point::new(now, 5.0),
+        ]);
+        let expected = get_database(vec![
+            Point::new(now - 299, 0.0),
+            Point::new(now - 180, 2.0),
+            Point::new(now - 120, 3.0),
+            Point::default(),
+            Point::new(now, 5.0),
+        ]);
+
+        let report = repair_at(&mut bytes, now).unwrap();
+        assert_eq!(
+            &[
+                Repair::RewroteBase {
+                    archive: 0,
+                    point: Point::new(now + 60, 1.0),
+                    timestamp: now - 299,
+                },
+                Repair::ClearedPoint {
+                    archive: 0,
+                    index: 3,
+                    point: Point::new(now - 600, 4.0),
+                },
+            ],
+            report.repairs()
+        );
+        assert_eq!(expected, bytes);
+
+        // The marker is recognized when repairing again
+        assert!(repair_at(&mut bytes, now).unwrap().is_unchanged());
+    }
+
+    #[test]
+    fn test_repair_points_empty_base() {
+        let now = 1500000000;
+        let mut bytes = get_database(vec![
+            Point::default(),
+            Point::new(now - 60, 1.0),
+            Point::default(),
+            Point::default(),
+            Point::default(),
+        ]);
+
+        let report = repair_at(&mut bytes, now).unwrap();
+        assert_eq!(
+            &[Repair::RewroteBase {
+                archive: 0,
+                point: Point::default(),
+                timestamp: now - 179,
+            }],
+            report.repairs()
+        );
+        assert_eq!(
+            get_database(vec![
+                Point::new(now - 179, 0.0),
+                Point::new(now - 60, 1.0),
+                Point::default(),
+                Point::default(),
+                Point::default(),
+            ]),
+            bytes
+        );
+    }
+
+    #[test]
+    fn test_repair_points_no_plausible_points() {
+        let now = 1500000000;
+        let mut bytes = get_database(vec![
+            Point::new(now + 60, 1.0),
+            Point::new(now - 59, 2.0),
+            Point::default(),
+            Point::default(),
+            Point::default(),
+        ]);
+
+        let report = repair_at(&mut bytes, now).unwrap();
+        assert_eq!(2, report.repairs().len());
+        assert_eq!(get_database(vec![Point::default(); 5]), bytes);
+    }
+
+    #[test]
+    fn test_repair_file_size() {
+        let now = 1500000000;
+        let expected = get_database(vec![Point::default(); 5]);
+
+        let mut bytes = expected[..expected.len() - 20].to_vec();
+        let report = repair_at(&mut bytes, now).unwrap();
+        assert_eq!(
+            &[Repair::Extended {
+                from: expected.len() as u64 - 20,
+                to: expected.len() as u64,
+            }],
+            report.repairs()
+        );
+        assert_eq!(expected, bytes);
+
+        let mut bytes = expected.clone();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        let report = repair_at(&mut bytes, now).unwrap();
+        assert_eq!(
+            &[Repair::Truncated {
+                from: expected.len() as u64 + 3,
+                to: expected.len() as u64,
+            }],
+            report.repairs()
+        );
+        assert_eq!(expected, bytes);
+    }
+
+    #[test]
+    fn test_repair_points_one_second_precision() {
+        let now = 1500000000;
+        let header = HeaderBuilder::new()
+            .with_archives(vec![ArchiveInfo::new(0, 1, 3)])
+            .build();
+        let mut bytes = vec![];
+        memento_encode_header(&mut bytes, &header).unwrap();
+        memento_encode_archive(
+            &mut bytes,
+            &Archive::new(vec![
+                Point::new(now + 1, 1.0),
+                Point::new(now - 1, 2.0),
+                Point::new(now, 3.0),
+            ]),
+        )
+        .unwrap();
+
+        // There's no marker for archives with one second precision, so the
+        // first slot can only be cleared.
+        let report = repair_at(&mut bytes, now).unwrap();
+        assert_eq!(
+            &[Repair::ClearedPoint {
+                archive: 0,
+                index: 0,
+                point: Point::new(now + 1, 1.0),
+            }],
+            report.repairs()
+        );
+    }
+
+    #[test]
+    fn test_repair_header_too_large() {
+        let header = HeaderBuilder::new()
+            .with_archives(vec![ArchiveInfo::new(0, 1, 100_000_000)])
+            .build();
+        let mut bytes = vec![];
+        memento_encode_header(&mut bytes, &header).unwrap();
+        let original = bytes.clone();
+
+        let res = repair_at(&mut bytes, 1500000000);
+        assert_eq!(ErrorKind::CorruptDatabase, res.unwrap_err().kind());
+        assert_eq!(original, bytes);
+    }
+
+    #[test]
+    fn test_repair_bad_header() {
+        let now = 1500000000;
+        let original = get_database(vec![Point::default(); 5]);
+
+        let mut bytes = original[..30].to_vec();
+        let res = repair_at(&mut bytes, now);
+        assert_eq!(ErrorKind::CorruptDatabase, res.unwrap_err().kind());
+        assert_eq!(&original[..30], &bytes[..]);
+
+        // Offset of the second archive
+        let mut bytes = original.clone();
+        bytes[31] += 12;
+        let res = repair_at(&mut bytes, now);
+        assert_eq!(ErrorKind::CorruptDatabase, res.unwrap_err().kind());
+    }
+}
@@ -7,3 +7,1626 @@
 // <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
+
+//! Functions to create and modify Whisper files on disk
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use memento_core::encoder::{memento_encode_header, memento_encode_metadata, memento_encode_point};
+use memento_core::errors::{ErrorKind, MementoError, MementoResult};
+use memento_core::parser::{
+    memento_parse_archive_infos, memento_parse_metadata, memento_parse_point,
+};
+use memento_core::types::{
+    AggregationType, ArchiveInfo, Header, HeaderBuilder, MementoDatabase, Metadata, Point,
+};
+
+use import::ImportMode;
+use lock::{lock_exclusive, LockMode};
+use read::MementoFileReader;
+use repair::{repair, RepairReport};
+
+// Size of the buffer of zeros written repeatedly to fill the data
+// section of a new database. Matches the chunk size used by whisper.py
+// when creating non-sparse files.
+const ZERO_FILL_CHUNK: usize = 16384;
+
+// Counter included in the names of temporary files so that each one created
+// by this process gets a unique name.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Write an entire database for the given header (with all data points
+/// set to zero) to the given writer.
+fn write_empty_database<W>(writer: &mut W, header: &Header) -> MementoResult<()>
+where
+    W: Write,
+{
+    memento_encode_header(writer, header)?;
+
+    let zeros = [0u8; ZERO_FILL_CHUNK];
+    let mut remaining = header.file_size() - header.size();
+
+    while remaining > 0 {
+        let len = if remaining > ZERO_FILL_CHUNK as u64 {
+            ZERO_FILL_CHUNK
+        } else {
+            remaining as usize
+        };
+
+        writer.write_all(&zeros[0..len])?;
+        remaining -= len as u64;
+    }
+
+    Ok(())
+}
+
+/// Write an empty database for the given header to a newly created file,
+/// making sure all data has been flushed to disk before returning.
+fn write_new_file(file: File, header: &Header) -> MementoResult<()> {
+    let mut writer = BufWriter::new(file);
+    write_empty_database(&mut writer, header)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    Ok(())
+}
+
+/// Read the header of a database from the start of the given file.
+fn read_header<F>(file: &mut F) -> MementoResult<Header>
+where
+    F: Read + Seek,
+{
+    let mut buf = vec![0u8; Metadata::storage() as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut buf)?;
+    let metadata = memento_parse_metadata(&buf).to_full_result()?;
+
+    buf.resize(metadata.archive_info_size() as usize, 0);
+    file.read_exact(&mut buf)?;
+    let infos = memento_parse_archive_infos(&buf, &metadata).to_full_result()?;
+
+    Ok(Header::new(metadata, infos))
+}
+
+/// Rewrite only the metadata block of a database with the result of applying
+/// `modify` to the existing metadata, returning the previous metadata.
+fn rewrite_metadata<F, M>(file: &mut F, modify: M) -> MementoResult<Metadata>
+where
+    F: Read + Write + Seek,
+    M: FnOnce(&Metadata) -> Metadata,
+{
+    let mut buf = vec![0u8; Metadata::storage() as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut buf)?;
+    let previous = memento_parse_metadata(&buf).to_full_result()?;
+
+    file.seek(SeekFrom::Start(0))?;
+    memento_encode_metadata(file, &modify(&previous))?;
+    Ok(previous)
+}
+
+/// Read `count` consecutive points from an archive starting at the absolute
+/// offset `start`, wrapping around to the beginning of the archive if the
+/// end of it is reached.
+fn read_points<F>(
+    file: &mut F,
+    info: &ArchiveInfo,
+    start: u64,
+    count: u32,
+) -> MementoResult<Vec<Point>>
+where
+    F: Read + Seek,
+{
+    let archive_end = u64::from(info.offset()) + info.archive_size();
+    let len = Point::storage() * u64::from(count);
+    let mut buf = vec![0u8; len as usize];
+
+    file.seek(SeekFrom::Start(start))?;
+    if start + len <= archive_end {
+        file.read_exact(&mut buf)?;
+    } else {
+        let (first, second) = buf.split_at_mut((archive_end - start) as usize);
+        file.read_exact(first)?;
+        file.seek(SeekFrom::Start(u64::from(info.offset())))?;
+        file.read_exact(second)?;
+    }
+
+    let mut points = Vec::with_capacity(count as usize);
+    for bytes in buf.chunks(Point::storage() as usize) {
+        points.push(memento_parse_point(bytes).to_full_result()?);
+    }
+
+    Ok(points)
+}
+
+/// Write a single point to the given absolute offset.
+fn write_point<F>(file: &mut F, offset: u64, point: &Point) -> MementoResult<()>
+where
+    F: Write + Seek,
+{
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(memento_encode_point(file, point)?)
+}
+
+/// Write consecutive points to an archive starting at the absolute offset
+/// `start`, wrapping around to the beginning of the archive if the end of
+/// it is reached.
+fn write_points<F>(
+    file: &mut F,
+    info: &ArchiveInfo,
+    start: u64,
+    points: &[Point],
+) -> MementoResult<()>
+where
+    F: Write + Seek,
+{
+    let archive_end = u64::from(info.offset()) + info.archive_size();
+    let mut buf = Vec::with_capacity(points.len() * Point::storage() as usize);
+    for point in points {
+        memento_encode_point(&mut buf, point)?;
+    }
+
+    file.seek(SeekFrom::Start(start))?;
+    if start + buf.len() as u64 <= archive_end {
+        file.write_all(&buf)?;
+    } else {
+        let (first, second) = buf.split_at((archive_end - start) as usize);
+        file.write_all(first)?;
+        file.seek(SeekFrom::Start(u64::from(info.offset())))?;
+        file.write_all(second)?;
+    }
+
+    Ok(())
+}
+
+/// Read the timestamp of the first point of an archive (the "base" point).
+///
+/// The base timestamp determines which slot of the archive each interval
+/// is stored in. It is zero for archives that have never been written to.
+fn read_base<F>(file: &mut F, info: &ArchiveInfo) -> MementoResult<u32>
+where
+    F: Read + Seek,
+{
+    let points = read_points(file, info, u64::from(info.offset()), 1)?;
+    Ok(points[0].timestamp())
+}
+
+/// Get the absolute offset of the slot in an archive for the given interval
+/// based on the timestamp of the base point of the archive.
+///
+/// An archive that has never been written to has a base timestamp of zero
+/// and the interval is placed in the first slot.
+fn point_offset(info: &ArchiveInfo, base: u32, interval: u32) -> u64 {
+    if base == 0 {
+        return u64::from(info.offset());
+    }
+
+    // Time distance may be negative when the interval is older than the
+    // base point. Floored division and a positive modulus are used here to
+    // match the behavior of whisper.py, which relies on Python semantics.
+    let time_distance = i64::from(interval) - i64::from(base);
+    let point_distance = time_distance.div_euclid(i64::from(info.seconds_per_point()));
+    let byte_distance = point_distance * Point::storage() as i64;
+    u64::from(info.offset()) + byte_distance.rem_euclid(info.archive_size() as i64) as u64
+}
+
+/// Align a timestamp to the start of the interval that contains it.
+#[inline]
+fn align(timestamp: u32, seconds_per_point: u32) -> u32 {
+    timestamp - (timestamp % seconds_per_point)
+}
+
+/// Aggregate the points of the `higher` archive that fall within the interval
+/// of the `lower` archive containing `timestamp` and write the result to the
+/// `lower` archive.
+///
+/// Return `true` if a value was written to the lower archive or `false` if
+/// there were not enough known values in the higher archive (based on the
+/// x-files-factor of the database) to compute one.
+fn propagate<F>(
+    file: &mut F,
+    header: &Header,
+    timestamp: u32,
+    higher: &ArchiveInfo,
+    lower: &ArchiveInfo,
+) -> MementoResult<bool>
+where
+    F: Read + Write + Seek,
+{
+    let metadata = header.metadata();
+    let lower_interval = align(timestamp, lower.seconds_per_point());
+
+    let higher_base = read_base(file, higher)?;
+    let higher_first = point_offset(higher, higher_base, lower_interval);
+    let higher_points = lower.seconds_per_point() / higher.seconds_per_point();
+    let series = read_points(file, higher, higher_first, higher_points)?;
+
+    // Only points with the timestamp expected for their position in the
+    // series are valid, anything else is left over from a previous trip
+    // around the ring buffer.
+    let mut expected = lower_interval;
+    let mut neighbors = Vec::with_capacity(series.len());
+    for point in &series {
+        if point.timestamp() == expected {
+            neighbors.push(Some(point.value()));
+        } else {
+            neighbors.push(None);
+        }
+
+        expected += higher.seconds_per_point();
+    }
+
+    let value = match metadata
+        .aggregation()
+        .aggregate_series(&neighbors, metadata.x_files_factor())
+    {
+        Some(v) => v,
+        None => return Ok(false),
+    };
+
+    let lower_base = read_base(file, lower)?;
+    let lower_offset = point_offset(lower, lower_base, lower_interval);
+    write_point(file, lower_offset, &Point::new(lower_interval, value))?;
+
+    Ok(true)
+}
+
+/// Write a single value to the highest precision archive able to store it
+/// and propagate it to all lower precision archives.
+///
+/// `now` is used to determine the age of the timestamp and hence which
+/// archives are able to store the value.
+fn update_point<F>(
+    file: &mut F,
+    header: &Header,
+    timestamp: i64,
+    value: f64,
+    now: i64,
+) -> MementoResult<()>
+where
+    F: Read + Write + Seek,
+{
+    let archives = header.archive_info();
+    let age = now - timestamp;
+
+    if age < 0 || age >= i64::from(header.metadata().max_retention()) {
+        return Err(MementoError::from((
+            ErrorKind::NoArchiveAvailable,
+            "timestamp not covered by any archive",
+        )));
+    }
+
+    let index = archives
+        .iter()
+        .position(|a| a.retention() as i64 >= age)
+        .ok_or_else(|| {
+            MementoError::from((
+                ErrorKind::NoArchiveAvailable,
+                "timestamp not covered by any archive",
+            ))
+        })?;
+
+    let info = &archives[index];
+    let interval = align(timestamp as u32, info.seconds_per_point());
+    let base = read_base(file, info)?;
+    let offset = point_offset(info, base, interval);
+    write_point(file, offset, &Point::new(interval, value))?;
+
+    for (higher, lower) in archives[index..].iter().zip(&archives[index + 1..]) {
+        if !propagate(file, header, interval, higher, lower)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write multiple points, sorted from oldest to newest, to the archive at
+/// `index` and propagate them to all lower precision archives.
+///
+/// Points are aligned to the resolution of the archive. When multiple points
+/// end up with the same interval, the newest one is kept. Each contiguous run
+/// of intervals is written with a single write and each affected interval of
+/// the lower precision archives is only propagated once.
+fn archive_update_many<F>(
+    file: &mut F,
+    header: &Header,
+    index: usize,
+    points: &[Point],
+) -> MementoResult<()>
+where
+    F: Read + Write + Seek,
+{
+    let archives = header.archive_info();
+    let info = &archives[index];
+    let step = info.seconds_per_point();
+
+    let aligned: Vec<Point> = points
+        .iter()
+        .map(|p| Point::new(align(p.timestamp(), step), p.value()))
+        .collect();
+
+    let mut runs: Vec<Vec<Point>> = Vec::new();
+    for (i, point) in aligned.iter().enumerate() {
+        // Take the last point in a run of points with duplicate intervals
+        if i + 1 < aligned.len() && point.timestamp() == aligned[i + 1].timestamp() {
+            continue;
+        }
+
+        let contiguous = match runs.last() {
+            Some(run) => point.timestamp() == run[run.len() - 1].timestamp() + step,
+            None => false,
+        };
+
+        if contiguous {
+            let last = runs.len() - 1;
+            runs[last].push(point.clone());
+        } else {
+            runs.push(vec![point.clone()]);
+        }
+    }
+
+    if runs.is_empty() {
+        return Ok(());
+    }
+
+    // If this archive has never been written to, use the start of the oldest
+    // run as the base so that it ends up at the start of the archive.
+    let base = match read_base(file, info)? {
+        0 => runs[0][0].timestamp(),
+        v => v,
+    };
+
+    for run in &runs {
+        let offset = point_offset(info, base, run[0].timestamp());
+        write_points(file, info, offset, run)?;
+    }
+
+    let mut higher = info;
+    for lower in &archives[index + 1..] {
+        let intervals: BTreeSet<u32> = aligned
+            .iter()
+            .map(|p| align(p.timestamp(), lower.seconds_per_point()))
+            .collect();
+
+        let mut propagated = false;
+        for interval in intervals {
+            if propagate(file, header, interval, higher, lower)? {
+                propagated = true;
+            }
+        }
+
+        if !propagated {
+            break;
+        }
+
+        higher = lower;
+    }
+
+    Ok(())
+}
+
+/// Write multiple points to the archives able to store them and propagate
+/// them to all lower precision archives.
+///
+/// Each point is written to the highest precision archive with enough
+/// retention to store it based on `now`. Points that are too old for any
+/// archive are silently dropped.
+fn update_many_points<F>(
+    file: &mut F,
+    header: &Header,
+    points: &[Point],
+    now: i64,
+) -> MementoResult<()>
+where
+    F: Read + Write + Seek,
+{
+    // Newest points first. This is a stable sort so points with the same
+    // timestamp keep their relative order, matching whisper.py.
+    let mut points = points.to_vec();
+    points.sort_by_key(|p| Reverse(p.timestamp()));
+
+    let mut archives = header.archive_info().iter().enumerate();
+    let mut current = archives.next();
+    let mut current_points = Vec::new();
+
+    for point in points {
+        let age = now - i64::from(point.timestamp());
+
+        while let Some((index, info)) = current {
+            if info.retention() as i64 >= age {
+                break;
+            }
+
+            // This archive can't fit any more points, commit the ones we've
+            // found so far that it can fit (oldest first).
+            if !current_points.is_empty() {
+                current_points.reverse();
+                archive_update_many(file, header, index, &current_points)?;
+                current_points.clear();
+            }
+
+            current = archives.next();
+        }
+
+        if current.is_none() {
+            // Drop remaining points that don't fit in the database
+            break;
+        }
+
+        current_points.push(point);
+    }
+
+    if let Some((index, _)) = current {
+        if !current_points.is_empty() {
+            current_points.reverse();
+            archive_update_many(file, header, index, &current_points)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Convert the valid points of a `source` archive to the resolution of a
+/// `target` archive, adding them to `values` keyed by target interval.
+///
+/// Points from a higher resolution source are aggregated with `method` and
+/// only kept if enough of them are known based on `x_files_factor`. Points
+/// from a lower resolution source are placed in the single target interval
+/// that contains them rather than being repeated across several intervals.
+fn resample(
+    method: AggregationType,
+    x_files_factor: f32,
+    source: &ArchiveInfo,
+    points: &[Point],
+    target: &ArchiveInfo,
+    now: u32,
+    values: &mut BTreeMap<u32, f64>,
+) {
+    let source_step = source.seconds_per_point();
+    let target_step = target.seconds_per_point();
+    let oldest = u64::from(now).saturating_sub(source.retention()) as u32;
+
+    // Points outside the window covered by the archive are left over from
+    // a previous trip around the ring buffer (or were never written).
+    let valid = points.iter().filter(|p| {
+        p.timestamp() != 0
+            && p.timestamp() > oldest
+            && p.timestamp() <= now
+            && p.timestamp() % source_step == 0
+    });
+
+    if source_step >= target_step {
+        for point in valid {
+            values.insert(align(point.timestamp(), target_step), point.value());
+        }
+
+        return;
+    }
+
+    let slots = (target_step / source_step) as usize;
+    let mut intervals: BTreeMap<u32, Vec<Option<f64>>> = BTreeMap::new();
+    for point in valid {
+        let interval = align(point.timestamp(), target_step);
+        let slot = ((point.timestamp() - interval) / source_step) as usize;
+        let neighbors = intervals
+            .entry(interval)
+            .or_insert_with(|| vec![None; slots]);
+        neighbors[slot.min(slots - 1)] = Some(point.value());
+    }
+
+    for (interval, neighbors) in intervals {
+        if let Some(value) = method.aggregate_series(&neighbors, x_files_factor) {
+            values.insert(interval, value);
+        }
+    }
+}
+
+/// Write the points of an existing database to a new database with the
+/// archives described by `header`, re-aggregating them to the resolution of
+/// each new archive with the aggregation method and x-files-factor of the
+/// existing database.
+///
+/// Each interval of a new archive uses the highest precision data available
+/// for it. Values are written directly to each new archive without being
+/// propagated, since each archive is computed from the existing data.
+fn resize_database<F, W>(
+    source: &mut F,
+    dest: &mut W,
+    header: &Header,
+    now: u32,
+) -> MementoResult<()>
+where
+    F: Read + Seek,
+    W: Write + Seek,
+{
+    let existing = read_header(source)?;
+    let metadata = existing.metadata();
+
+    let mut archives = Vec::with_capacity(existing.archive_info().len());
+    for info in existing.archive_info() {
+        let points = read_points(source, info, u64::from(info.offset()), info.num_points())?;
+        archives.push((info, points));
+    }
+
+    write_empty_database(dest, header)?;
+
+    for target in header.archive_info() {
+        // Lowest precision data first so that higher precision data
+        // replaces it for any intervals both of them have values for.
+        let mut values = BTreeMap::new();
+        for &(info, ref points) in archives.iter().rev() {
+            resample(
+                metadata.aggregation(),
+                metadata.x_files_factor(),
+                info,
+                points,
+                target,
+                now,
+                &mut values,
+            );
+        }
+
+        let oldest = u64::from(now).saturating_sub(target.retention()) as u32;
+        let mut intervals = values.range(oldest + 1..).peekable();
+        let base = match intervals.peek() {
+            Some(&(&interval, _)) => interval,
+            None => continue,
+        };
+
+        let mut slots = vec![Point::default(); target.num_points() as usize];
+        for (&interval, &value) in intervals {
+            let offset = point_offset(target, base, interval) - u64::from(target.offset());
+            slots[(offset / Point::storage()) as usize] = Point::new(interval, value);
+        }
+
+        write_points(dest, target, u64::from(target.offset()), &slots)?;
+    }
+
+    Ok(())
+}
+
+/// How points copied from another database are written to existing archives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CopyMode {
+    /// Replace any existing value for an interval.
+    Overwrite,
+    /// Only write intervals that don't have a value yet.
+    Fill,
+}
+
+/// Copy the points of the `source` database to each archive of a database,
+/// re-aggregating them to the resolution of each archive with the aggregation
+/// method and x-files-factor of the database being written.
+///
+/// Each interval uses the highest precision data available for it in the
+/// source database and only intervals within the retention of an archive
+/// (based on `now`) are written. Values are written directly to each archive
+/// without being propagated. Return the number of points written to each
+/// archive.
+fn copy_points<F>(
+    file: &mut F,
+    header: &Header,
+    source: &MementoDatabase,
+    mode: CopyMode,
+    now: u32,
+) -> MementoResult<Vec<usize>>
+where
+    F: Read + Write + Seek,
+{
+    let metadata = header.metadata();
+    let sources: Vec<_> = source
+        .header()
+        .archive_info()
+        .iter()
+        .zip(source.data().archives())
+        .collect();
+
+    let mut written = Vec::with_capacity(header.archive_info().len());
+    for target in header.archive_info() {
+        let mut values = BTreeMap::new();
+        for &(info, archive) in sources.iter().rev() {
+            resample(
+                metadata.aggregation(),
+                metadata.x_files_factor(),
+                info,
+                archive.points(),
+                target,
+                now,
+                &mut values,
+            );
+        }
+
+        let oldest = u64::from(now).saturating_sub(target.retention()) as u32;
+        let values: Vec<(u32, f64)> = values.range(oldest + 1..).map(|(&k, &v)| (k, v)).collect();
+        written.push(write_values(file, target, &values, mode)?);
+    }
+
+    Ok(written)
+}
+
+/// Write a value for each of the given intervals, ordered from oldest to
+/// newest, directly to an archive without propagating them. Return the
+/// number of values written, which may be fewer than given with
+/// `CopyMode::Fill`.
+fn write_values<F>(
+    file: &mut F,
+    target: &ArchiveInfo,
+    values: &[(u32, f64)],
+    mode: CopyMode,
+) -> MementoResult<usize>
+where
+    F: Read + Write + Seek,
+{
+    if values.is_empty() {
+        return Ok(0);
+    }
+
+    let start = u64::from(target.offset());
+    let existing = read_points(file, target, start, target.num_points())?;
+
+    // If this archive has never been written to, use the oldest interval
+    // as the base so that it ends up at the start of the archive.
+    let base = match existing[0].timestamp() {
+        0 => values[0].0,
+        v => v,
+    };
+
+    let mut count = 0;
+    for &(interval, value) in values {
+        let offset = point_offset(target, base, interval);
+        let slot = ((offset - start) / Point::storage()) as usize;
+
+        if mode == CopyMode::Fill && existing[slot].timestamp() == interval {
+            continue;
+        }
+
+        write_point(file, offset, &Point::new(interval, value))?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Convert a series of imported points to the values to write to each
+/// archive described by `header`, in the same order as the archives.
+///
+/// Points are first aligned to the precision of the highest precision
+/// archive so that points that don't fall exactly on an interval still end
+/// up with one value per interval. When several points end up in the same
+/// interval, the last one given is used. Each archive then gets a value for
+/// every interval within its retention that contains any of these points,
+/// aggregated with the aggregation method of the database. The x-files-factor
+/// isn't applied since the imported points are all that is known about each
+/// interval, e.g. hourly points imported into a per minute archive would
+/// otherwise never be enough to fill an hourly archive.
+fn import_values(points: &[Point], header: &Header, now: u32) -> Vec<Vec<(u32, f64)>> {
+    let step = header
+        .archive_info()
+        .first()
+        .map_or(1, |info| info.seconds_per_point());
+
+    let mut aligned = BTreeMap::new();
+    for point in points {
+        aligned.insert(align(point.timestamp(), step), point.value());
+    }
+
+    let method = header.metadata().aggregation();
+    header
+        .archive_info()
+        .iter()
+        .map(|info| {
+            let oldest = u64::from(now).saturating_sub(info.retention()) as u32;
+            let mut intervals: BTreeMap<u32, Vec<f64>> = BTreeMap::new();
+            for (&timestamp, &value) in aligned.range(oldest + 1..=now) {
+                intervals
+                    .entry(align(timestamp, info.seconds_per_point()))
+                    .or_default()
+                    .push(value);
+            }
+
+            intervals
+                .into_iter()
+                .filter_map(|(interval, known)| method.aggregate(&known).map(|v| (interval, v)))
+                .collect()
+        })
+        .collect()
+}
+
+/// Get a path in the same directory as `path` with `suffix` appended to the
+/// file name, e.g. `foo.wsp` and `bak` results in `foo.wsp.bak`.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Create a new file in the same directory as `path` that a replacement for
+/// it can be written to, returning the path of the new file.
+///
+/// The name of the file includes the process ID and a counter so that
+/// concurrent writers don't collide. Names that are already taken, such as
+/// files left behind by a process that crashed, are skipped.
+fn create_temp_file(path: &Path) -> MementoResult<(PathBuf, File)> {
+    loop {
+        let count = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tmp = sibling_path(path, &format!("{}.{}.tmp", process::id(), count));
+        match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&tmp)
+        {
+            Ok(file) => return Ok((tmp, file)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(MementoError::from(e)),
+        }
+    }
+}
+
+/// Write the points of an existing database to a newly created file with the
+/// archives described by `header`, making sure all data has been flushed to
+/// disk before returning. The new file gets the permissions of the existing one.
+fn write_resized_file(file: &mut File, dest: File, header: &Header, now: u32) -> MementoResult<()> {
+    dest.set_permissions(file.metadata()?.permissions())?;
+
+    let mut writer = BufWriter::new(dest);
+    resize_database(file, &mut writer, header, now)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    Ok(())
+}
+
+/// Write the contents of a repaired database to a newly created file, making
+/// sure all data has been flushed to disk before returning. The new file gets
+/// the permissions of the existing one.
+fn write_repaired_file(file: &File, mut dest: File, bytes: &[u8]) -> MementoResult<()> {
+    dest.set_permissions(file.metadata()?.permissions())?;
+    dest.write_all(bytes)?;
+    dest.sync_all()?;
+    Ok(())
+}
+
+/// Atomically replace the file at `path` with the file at `tmp`, optionally
+/// keeping the file being replaced as a backup with a `.bak` suffix.
+fn replace_file(path: &Path, tmp: &Path, keep_backup: bool) -> MementoResult<()> {
+    if keep_backup {
+        let backup = sibling_path(path, "bak");
+        if let Err(e) = fs::remove_file(&backup) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(MementoError::from(e));
+            }
+        }
+
+        // A hard link is used so that there's never a moment where no
+        // database exists at `path`.
+        fs::hard_link(path, &backup)?;
+    }
+
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// Create and modify whisper database files on disk, optionally acquiring
+/// an exclusive lock on each file while it is being written.
+#[derive(Debug)]
+pub struct MementoFileWriter {
+    lock: LockMode,
+}
+
+impl MementoFileWriter {
+    /// Create a new writer that doesn't lock files.
+    pub fn new() -> Self {
+        MementoFileWriter {
+            lock: LockMode::None,
+        }
+    }
+
+    /// Use the given locking mode when writing files.
+    pub fn with_lock(mut self, val: LockMode) -> Self {
+        self.lock = val;
+        self
+    }
+
+    /// Open an existing file for reading and writing and lock it based on
+    /// the locking mode of this writer. The lock is held until the file is
+    /// closed.
+    fn open<P>(&self, path: P) -> MementoResult<File>
+    where
+        P: AsRef<Path>,
+    {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        lock_exclusive(&file, self.lock)?;
+        Ok(file)
+    }
+
+    /// Create a new whisper database file with the archives described by
+    /// the given header and all data points set to zero.
+    ///
+    /// Archives are sorted from highest to lowest precision and the archive
+    /// offsets, archive count, and maximum retention of the header are computed
+    /// based on them, any existing values for them are ignored. The header that
+    /// was actually written to the file is returned.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if the archives are not a valid configuration
+    /// for a database (see `Header::validate`), if the file already exists, if
+    /// it could not be locked, or if there were any I/O errors creating or
+    /// writing the file (such as permission errors).
+    /// If the file was created but could not be completely written, it will
+    /// be removed.
+    pub fn create<P>(&self, path: P, header: &Header) -> MementoResult<Header>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let header = HeaderBuilder::from(header).build();
+        header.validate()?;
+
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let res = lock_exclusive(&file, self.lock).and_then(|_| write_new_file(file, &header));
+
+        if let Err(e) = res {
+            // Don't leave a partially written database around where it
+            // might be mistaken for a valid one. There's nothing useful
+            // we can do if removing it fails so the original error is
+            // returned regardless.
+            let _ = fs::remove_file(path);
+            return Err(e);
+        }
+
+        Ok(header)
+    }
+
+    /// Write a single value to a whisper database file at the given time
+    /// and propagate it to all lower precision archives.
+    ///
+    /// The value is written to the highest precision archive with enough
+    /// retention to store it, with the timestamp aligned to the resolution
+    /// of the archive. Lower precision archives are updated using the
+    /// aggregation method and x-files-factor of the database.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if there were any I/O errors reading or writing
+    /// the database file (such as permission errors), if the file was malformed
+    /// or could not be locked, or if the timestamp is in the future or older
+    /// than the maximum retention of the database.
+    pub fn update<P, T>(&self, path: P, value: f64, timestamp: DateTime<T>) -> MementoResult<()>
+    where
+        P: AsRef<Path>,
+        T: TimeZone,
+    {
+        let mut file = self.open(path)?;
+        let header = read_header(&mut file)?;
+        let now = Utc::now().timestamp();
+        update_point(&mut file, &header, timestamp.timestamp(), value, now)
+    }
+
+    /// Write multiple points to a whisper database file and propagate them
+    /// to all lower precision archives.
+    ///
+    /// Points do not need to be sorted. They are grouped by the highest
+    /// precision archive with enough retention to store each of them and
+    /// written to each archive using as few writes as possible. Points that
+    /// are older than the maximum retention of the database are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if there were any I/O errors reading or writing
+    /// the database file (such as permission errors) or if the file was
+    /// malformed or could not be locked.
+    pub fn update_many<P>(&self, path: P, points: &[Point]) -> MementoResult<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = self.open(path)?;
+        let header = read_header(&mut file)?;
+        let now = Utc::now().timestamp();
+        update_many_points(&mut file, &header, points, now)
+    }
+
+    /// Change the aggregation method of a whisper database file, returning
+    /// the previous aggregation method.
+    ///
+    /// Only the metadata of the file is rewritten. Existing values in lower
+    /// precision archives are not changed, the new method is only used when
+    /// propagating values written afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if there were any I/O errors reading or writing
+    /// the database file (such as permission errors) or if the file was
+    /// malformed or could not be locked.
+    pub fn set_aggregation<P>(
+        &self,
+        path: P,
+        val: AggregationType,
+    ) -> MementoResult<AggregationType>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = self.open(path)?;
+        let previous = rewrite_metadata(&mut file, |meta| {
+            Metadata::new(
+                val,
+                meta.max_retention(),
+                meta.x_files_factor(),
+                meta.archive_count(),
+            )
+        })?;
+
+        Ok(previous.aggregation())
+    }
+
+    /// Change the x-files-factor of a whisper database file, returning the
+    /// previous x-files-factor.
+    ///
+    /// Only the metadata of the file is rewritten. Existing values in lower
+    /// precision archives are not changed, the new x-files-factor is only
+    /// used when propagating values written afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if the x-files-factor is not between `0.0` and
+    /// `1.0` (inclusive), if there were any I/O errors reading or writing the
+    /// database file (such as permission errors), or if the file was malformed
+    /// or could not be locked.
+    pub fn set_x_files_factor<P>(&self, path: P, val: f32) -> MementoResult<f32>
+    where
+        P: AsRef<Path>,
+    {
+        if !(0.0..=1.0).contains(&val) {
+            return Err(MementoError::from((
+                ErrorKind::InvalidXFilesFactor,
+                "x-files-factor must be between 0 and 1",
+                val.to_string(),
+            )));
+        }
+
+        let mut file = self.open(path)?;
+        let previous = rewrite_metadata(&mut file, |meta| {
+            Metadata::new(
+                meta.aggregation(),
+                meta.max_retention(),
+                val,
+                meta.archive_count(),
+            )
+        })?;
+
+        Ok(previous.x_files_factor())
+    }
+
+    /// Rewrite a whisper database file with the archives described by the
+    /// given header, carrying existing points over to the new archives.
+    ///
+    /// Archives are sorted and their offsets computed in the same way as
+    /// when creating a database. Existing points are re-aggregated to the
+    /// resolution of each new archive using the aggregation method and
+    /// x-files-factor of the existing file, while the new file uses the
+    /// aggregation method and x-files-factor of the given header. Points
+    /// outside the retention of a new archive are dropped.
+    ///
+    /// The new database is written to a temporary file in the same directory
+    /// which then atomically replaces the existing file. If `keep_backup` is
+    /// set, the existing file is kept with a `.bak` suffix, replacing any
+    /// previous backup. The header that was actually written is returned.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if the archives are not a valid configuration
+    /// for a database (see `Header::validate`), if there were any I/O errors
+    /// reading or writing the files involved (such as permission errors), or
+    /// if the existing file was malformed or could not be locked. The existing
+    /// file is left unchanged and the temporary file is removed on error.
+    pub fn resize<P>(&self, path: P, header: &Header, keep_backup: bool) -> MementoResult<Header>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let header = HeaderBuilder::from(header).build();
+        header.validate()?;
+
+        let mut file = self.open(path)?;
+        let now = Utc::now().timestamp() as u32;
+        let (tmp, dest) = create_temp_file(path)?;
+
+        let res = write_resized_file(&mut file, dest, &header, now)
+            .and_then(|_| replace_file(path, &tmp, keep_backup));
+        if let Err(e) = res {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+
+        Ok(header)
+    }
+
+    /// Copy all points from the `source` whisper database file to the `dest`
+    /// whisper database file, replacing any existing values in `dest`.
+    ///
+    /// The databases don't need to have the same archives. Points from the
+    /// source are re-aggregated to the resolution of each archive of the
+    /// destination using the aggregation method and x-files-factor of the
+    /// destination, using the highest precision data available for each
+    /// interval. Points outside the retention of a destination archive are
+    /// ignored.
+    ///
+    /// Return the number of points written to each archive of the destination,
+    /// in the same order as its archives.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if there were any I/O errors reading or writing
+    /// either of the files (such as permission errors) or if either file was
+    /// malformed or could not be locked.
+    pub fn merge<P, Q>(&self, source: P, dest: Q) -> MementoResult<Vec<usize>>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        self.copy(source, dest, CopyMode::Overwrite)
+    }
+
+    /// Copy points from the `source` whisper database file to the `dest`
+    /// whisper database file only for intervals that don't have a value in
+    /// `dest`, e.g. to backfill gaps in data after an outage.
+    ///
+    /// Points are converted between archives in the same way as `merge`.
+    /// Return the number of points written to each archive of the destination,
+    /// in the same order as its archives.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if there were any I/O errors reading or writing
+    /// either of the files (such as permission errors) or if either file was
+    /// malformed or could not be locked.
+    pub fn fill<P, Q>(&self, source: P, dest: Q) -> MementoResult<Vec<usize>>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        self.copy(source, dest, CopyMode::Fill)
+    }
+
+    /// Write imported points (e.g. from `import::read_csv`) to a whisper
+    /// database file, optionally creating it first.
+    ///
+    /// If the file doesn't exist and a header is given, a database with the
+    /// archives described by it is created first (see `create`). Points are
+    /// aligned to the precision of the highest precision archive, keeping
+    /// the last point given for each interval, and written directly to every
+    /// archive whose retention covers them, aggregated to the resolution of
+    /// each archive from the imported points alone. Points that aren't exactly
+    /// on an interval (e.g. from clock jitter) are kept this way rather than
+    /// being dropped. Existing values are handled based on `mode`, a file
+    /// that already existed is left unchanged with `ImportMode::Skip`.
+    ///
+    /// Return the number of points written to each archive, in the same
+    /// order as the archives of the file, or `None` if the file was skipped.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if the file doesn't exist and no header was
+    /// given, if the database could not be created (see `create`), if there
+    /// were any I/O errors reading or writing the file (such as permission
+    /// errors), or if the file was malformed or could not be locked.
+    pub fn import<P>(
+        &self,
+        path: P,
+        points: &[Point],
+        mode: ImportMode,
+        header: Option<&Header>,
+    ) -> MementoResult<Option<Vec<usize>>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let created = match header {
+            Some(header) if !path.exists() => {
+                self.create(path, header)?;
+                true
+            }
+            _ => false,
+        };
+
+        if !created && mode == ImportMode::Skip {
+            return Ok(None);
+        }
+
+        let mut file = self.open(path)?;
+        let header = read_header(&mut file)?;
+        let now = Utc::now().timestamp() as u32;
+
+        let mode = match mode {
+            ImportMode::Overwrite => CopyMode::Overwrite,
+            ImportMode::Fill | ImportMode::Skip => CopyMode::Fill,
+        };
+
+        let mut written = Vec::with_capacity(header.archive_info().len());
+        for (info, values) in header
+            .archive_info()
+            .iter()
+            .zip(import_values(points, &header, now))
+        {
+            written.push(write_values(&mut file, info, &values, mode)?);
+        }
+
+        Ok(Some(written))
+    }
+
+    /// Repair a truncated or partially corrupt whisper database file,
+    /// returning every change that was made (see `repair::repair`).
+    ///
+    /// The file is extended or truncated to the size required by its header
+    /// and slots with impossible timestamps are set to zero. If any changes
+    /// are needed, the repaired database is written to a temporary file in
+    /// the same directory which then atomically replaces the existing file.
+    /// If `keep_backup` is set, the existing file is kept with a `.bak`
+    /// suffix, replacing any previous backup. Nothing is written if the file
+    /// doesn't need any changes.
+    ///
+    /// # Errors
+    ///
+    /// Return an error result if the header of the file can't be trusted
+    /// to repair it (see `repair::repair`), if there were any I/O errors
+    /// reading or writing the files involved (such as permission errors), or
+    /// if the existing file could not be locked. The existing file is left
+    /// unchanged and the temporary file is removed on error.
+    pub fn repair<P>(&self, path: P, keep_backup: bool) -> MementoResult<RepairReport>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let mut file = self.open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let report = repair(&mut bytes)?;
+        if report.is_unchanged() {
+            return Ok(report);
+        }
+
+        let (tmp, dest) = create_temp_file(path)?;
+
+        let res = write_repaired_file(&file, dest, &bytes)
+            .and_then(|_| replace_file(path, &tmp, keep_backup));
+        if let Err(e) = res {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+
+        Ok(report)
+    }
+
+    fn copy<P, Q>(&self, source: P, dest: Q, mode: CopyMode) -> MementoResult<Vec<usize>>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        // The source is read completely (and unlocked) before the destination
+        // is locked so that the same file being given for both can't deadlock.
+        let reader = MementoFileReader::new().with_lock(self.lock);
+        let database = reader.read_database(source)?;
+
+        let mut file = self.open(dest)?;
+        let header = read_header(&mut file)?;
+        let now = Utc::now().timestamp() as u32;
+        copy_points(&mut file, &header, &database, mode, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+    use std::process;
+    use std::sync::atomic::Ordering;
+
+    use memento_core::errors::ErrorKind;
+    use memento_core::parser::memento_parse_database;
+    use memento_core::types::{
+        AggregationType, Archive, ArchiveInfo, Header, HeaderBuilder, MementoDatabase, Metadata,
+        Point,
+    };
+
+    use tempfile::TempDir;
+
+    use super::{
+        copy_points, create_temp_file, import_values, resize_database, rewrite_metadata,
+        update_many_points, update_point, write_empty_database, CopyMode, TEMP_FILE_COUNTER,
+    };
+
+    fn get_file_header() -> Header {
+        // Offsets, retention, and archive count are all intentionally wrong
+        // here since they are supposed to be computed when writing the file.
+        let metadata = Metadata::new(AggregationType::Sum, 0, 0.5, 0);
+        let info1 = ArchiveInfo::new(0, 60, 60 * 24);
+        let info2 = ArchiveInfo::new(0, 300, 12 * 24 * 7);
+
+        Header::new(metadata, vec![info1, info2])
+    }
+
+    fn get_database(aggregation: AggregationType, x_files_factor: f32) -> (Header, Vec<u8>) {
+        let metadata = Metadata::new(aggregation, 0, x_files_factor, 0);
+        let info1 = ArchiveInfo::new(0, 60, 10);
+        let info2 = ArchiveInfo::new(0, 300, 6);
+        let header = HeaderBuilder::from(&Header::new(metadata, vec![info1, info2])).build();
+
+        let mut buf = vec![];
+        write_empty_database(&mut buf, &header).unwrap();
+        (header, buf)
+    }
+
+    fn get_archives(buf: &[u8]) -> Vec<Archive> {
+        memento_parse_database(buf)
+            .unwrap()
+            .1
+            .data()
+            .archives()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_write_empty_database() {
+        let header = HeaderBuilder::from(&get_file_header()).build();
+        let mut buf = vec![];
+        write_empty_database(&mut buf, &header).unwrap();
+
+        assert_eq!(header.file_size(), buf.len() as u64);
+
+        let database = memento_parse_database(&buf).unwrap().1;
+        assert_eq!(&header, database.header());
+
+        for (info, archive) in header.archive_info().iter().zip(database.data().archives()) {
+            assert_eq!(info.num_points() as usize, archive.points().len());
+            assert!(archive.points().iter().all(|p| *p == Point::new(0, 0.0)));
+        }
+    }
+
+    #[test]
+    fn test_rewrite_metadata() {
+        let (header, buf) = get_database(AggregationType::Average, 0.5);
+        let mut cursor = Cursor::new(buf.clone());
+
+        let previous = rewrite_metadata(&mut cursor, |meta| {
+            Metadata::new(
+                AggregationType::Max,
+                meta.max_retention(),
+                0.1,
+                meta.archive_count(),
+            )
+        })
+        .unwrap();
+
+        let database = memento_parse_database(cursor.get_ref()).unwrap().1;
+        let metadata = database.header().metadata();
+
+        assert_eq!(header.metadata(), &previous);
+        assert_eq!(AggregationType::Max, metadata.aggregation());
+        assert_eq!(0.1, metadata.x_files_factor());
+        assert_eq!(header.archive_info(), database.header().archive_info());
+        assert_eq!(&buf[16..], &cursor.get_ref()[16..]);
+    }
+
+    #[test]
+    fn test_update_point_empty_database() {
+        let (header, buf) = get_database(AggregationType::Average, 0.5);
+        let now = 1500000000;
+        let mut cursor = Cursor::new(buf);
+
+        update_point(&mut cursor, &header, now - 30, 42.0, now).unwrap();
+        let archives = get_archives(cursor.get_ref());
+
+        // First write to an archive always goes in the first slot. A single
+        // known value out of five isn't enough to satisfy the x-files-factor
+        // so nothing is propagated to the lower precision archive.
+        assert_eq!(Point::new(1499999940, 42.0), archives[0].points()[0]);
+        assert!(archives[1].points().iter().all(|p| p.timestamp() == 0));
+    }
+
+    #[test]
+    fn test_update_point_wraps_archive() {
+        let (header, buf) = get_database(AggregationType::Average, 0.5);
+        let now = 1500000000;
+        let mut cursor = Cursor::new(buf);
+
+        update_point(&mut cursor, &header, now - 600, 1.0, now).unwrap();
+        update_point(&mut cursor, &header, now - 60, 2.0, now).unwrap();
+        update_point(&mut cursor, &header, now, 3.0, now).unwrap();
+        let archives = get_archives(cursor.get_ref());
+
+        // Ten slots in the first archive. The base point is nine intervals
+        // before the second point and ten before the third so the third
+        // point wraps around and replaces the base point.
+        assert_eq!(Point::new(1500000000, 3.0), archives[0].points()[0]);
+        assert_eq!(Point::new(1499999940, 2.0), archives[0].points()[9]);
+    }
+
+    #[test]
+    fn test_update_point_propagates() {
+        let (header, buf) = get_database(AggregationType::Sum, 0.5);
+        let now = 1500000000;
+        let mut cursor = Cursor::new(buf);
+
+        for (i, v) in [1.0, 2.0, 3.0].iter().enumerate() {
+            update_point(&mut cursor, &header, 1499999700 + i as i64 * 60, *v, now).unwrap();
+        }
+        let archives = get_archives(cursor.get_ref());
+
+        // The third value written makes three of the five values for the
+        // lower precision interval known, which satisfies the x-files-factor.
+        assert_eq!(Point::new(1499999700, 6.0), archives[1].points()[0]);
+    }
+
+    #[test]
+    fn test_update_point_not_covered() {
+        let (header, buf) = get_database(AggregationType::Average, 0.5);
+        let now = 1500000000;
+        let mut cursor = Cursor::new(buf);
+
+        let future = update_point(&mut cursor, &header, now + 1, 1.0, now);
+        let too_old = update_point(&mut cursor, &header, now - 1800, 1.0, now);
+
+        assert_eq!(ErrorKind::NoArchiveAvailable, future.unwrap_err().kind());
+        assert_eq!(ErrorKind::NoArchiveAvailable, too_old.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_update_many_points_runs() {
+        let (header, buf) = get_database(AggregationType::Average, 0.5);
+        let now = 1500000000;
+        let mut cursor = Cursor::new(buf);
+
+        let points = vec![
+            Point::new(1499999880, 3.0),
+            Point::new(1499999700, 1.0),
+            Point::new(1499999701, 1.5),
+            Point::new(1499999760, 2.0),
+            Point::new(1499999990, 5.0),
+        ];
+
+        update_many_points(&mut cursor, &header, &points, now).unwrap();
+        let archives = get_archives(cursor.get_ref());
+        let first = archives[0].points();
+
+        // The oldest point becomes the base of the archive and the newest
+        // of two points with the same interval is the one that's kept.
+        assert_eq!(Point::new(1499999700, 1.5), first[0]);
+        assert_eq!(Point::new(1499999760, 2.0), first[1]);
+        assert_eq!(Point::new(0, 0.0), first[2]);
+        assert_eq!(Point::new(1499999880, 3.0), first[3]);
+        assert_eq!(Point::new(1499999940, 5.0), first[4]);
+        assert_eq!(Point::new(0, 0.0), first[5]);
+
+        // Four of the five values for the first lower precision interval
+        // are known, the average of them is propagated.
+        assert_eq!(Point::new(1499999700, 2.875), archives[1].points()[0]);
+    }
+
+    #[test]
+    fn test_update_many_points_wraps_archive() {
+        let (header, buf) = get_database(AggregationType::Average, 0.5);
+        let now = 1500000000;
+        let mut cursor = Cursor::new(buf);
+
+        update_point(&mut cursor, &header, now - 480, 1.0, now).unwrap();
+        let points: Vec<Point> = (0..7)
+            .map(|i| Point::new(now as u32 - 120 + i * 60, f64::from(i)))
+            .collect();
+
+        update_many_points(&mut cursor, &header, &points, now + 240).unwrap();
+        let archives = get_archives(cursor.get_ref());
+        let first = archives[0].points();
+
+        // The base point is six slots before the start of the run, which is
+        // seven points long, so the last three points wrap to the start.
+        assert_eq!(Point::new(1499999880, 0.0), first[6]);
+        assert_eq!(Point::new(1500000060, 3.0), first[9]);
+        assert_eq!(Point::new(1500000120, 4.0), first[0]);
+        assert_eq!(Point::new(1500000240, 6.0), first[2]);
+        assert_eq!(Point::new(0, 0.0), first[3]);
+    }
+
+    #[test]
+    fn test_update_many_points_lower_archive() {
+        let (header, buf) = get_database(AggregationType::Max, 0.5);
+        let now = 1500000000;
+        let mut cursor = Cursor::new(buf);
+
+        let points = vec![
+            Point::new(now as u32 - 60, 1.0),
+            Point::new(now as u32 - 900, 2.0),
+            Point::new(now as u32 - 1200, 3.0),
+            Point::new(now as u32 - 3600, 4.0),
+        ];
+
+        update_many_points(&mut cursor, &header, &points, now).unwrap();
+        let archives = get_archives(cursor.get_ref());
+
+        // Points too old for the high precision archive are written to the
+        // lower precision one, points too old for any archive are dropped.
+        assert_eq!(Point::new(1499999940, 1.0), archives[0].points()[0]);
+        assert_eq!(Point::new(1499998800, 3.0), archives[1].points()[0]);
+        assert_eq!(Point::new(1499999100, 2.0), archives[1].points()[1]);
+        assert!(archives[1].points()[2..].iter().all(|p| p.timestamp() == 0));
+    }
+
+    #[test]
+    fn test_resize_database() {
+        let (header, buf) = get_database(AggregationType::Average, 0.5);
+        let now = 1500000000;
+        let mut source = Cursor::new(buf);
+
+        let points: Vec<Point> = (0..10)
+            .map(|i| Point::new(now - 60 * i, f64::from(i)))
+            .collect();
+        update_many_points(&mut source, &header, &points, i64::from(now)).unwrap();
+
+        let metadata = Metadata::new(AggregationType::Max, 0, 0.0, 0);
+        let info1 = ArchiveInfo::new(0, 120, 10);
+        let info2 = ArchiveInfo::new(0, 600, 4);
+        let resized = HeaderBuilder::from(&Header::new(metadata, vec![info1, info2])).build();
+
+        let mut dest = Cursor::new(vec![]);
+        resize_database(&mut source, &mut dest, &resized, now).unwrap();
+
+        let database = memento_parse_database(dest.get_ref()).unwrap().1;
+        let archives = database.data().archives();
+        let written = |archive: &Archive| {
+            let mut points: Vec<Point> = archive
+                .points()
+                .iter()
+                .filter(|p| p.timestamp() != 0)
+                .cloned()
+                .collect();
+            points.sort_by_key(|p| p.timestamp());
+            points
+        };
+
+        assert_eq!(&resized, database.header());
+
+        // Pairs of points from the 60 second archive are averaged (using the
+        // aggregation method of the original database) into each interval,
+        // replacing the values from the lower precision 300 second archive.
+        assert_eq!(
+            vec![
+                Point::new(now - 600, 9.0),
+                Point::new(now - 480, 7.5),
+                Point::new(now - 360, 5.5),
+                Point::new(now - 240, 3.5),
+                Point::new(now - 120, 1.5),
+                Point::new(now, 0.0),
+            ],
+            written(&archives[0])
+        );
+
+        // Nine of the ten points from the 60 second archive are known for the
+        // older interval, only one is known for the current interval which
+        // isn't enough for the x-files-factor of the original database.
+        assert_eq!(vec![Point::new(now - 600, 5.0)], written(&archives[1]));
+    }
+
+    fn get_copy_databases(now: u32) -> (MementoDatabase, Header, Vec<u8>) {
+        let (header, buf) = get_database(AggregationType::Average, 0.5);
+        let mut source = Cursor::new(buf);
+        let points: Vec<Point> = (0..10)
+            .map(|i| Point::new(now - 60 * i, f64::from(i)))
+            .collect();
+        update_many_points(&mut source, &header, &points, i64::from(now)).unwrap();
+        let database = memento_parse_database(source.get_ref()).unwrap().1;
+
+        let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 0);
+        let info1 = ArchiveInfo::new(0, 120, 10);
+        let info2 = ArchiveInfo::new(0, 600, 4);
+        let header = HeaderBuilder::from(&Header::new(metadata, vec![info1, info2])).build();
+        let mut dest = Cursor::new(vec![]);
+        write_empty_database(&mut dest, &header).unwrap();
+        update_point(
+            &mut dest,
+            &header,
+            i64::from(now - 120),
+            100.0,
+            i64::from(now),
+        )
+        .unwrap();
+
+        (database, header, dest.into_inner())
+    }
+
+    #[test]
+    fn test_copy_points_overwrite() {
+        let now = 1500000000;
+        let (source, header, buf) = get_copy_databases(now);
+        let mut dest = Cursor::new(buf);
+
+        let written = copy_points(&mut dest, &header, &source, CopyMode::Overwrite, now).unwrap();
+        let archives = get_archives(dest.get_ref());
+
+        assert_eq!(vec![6, 1], written);
+        assert!(archives[0].points().contains(&Point::new(now - 120, 1.5)));
+        assert!(archives[0].points().contains(&Point::new(now - 600, 9.0)));
+        assert!(archives[1].points().contains(&Point::new(now - 600, 5.0)));
+    }
+
+    #[test]
+    fn test_copy_points_fill() {
+        let now = 1500000000;
+        let (source, header, buf) = get_copy_databases(now);
+        let mut dest = Cursor::new(buf);
+
+        let written = copy_points(&mut dest, &header, &source, CopyMode::Fill, now).unwrap();
+        let archives = get_archives(dest.get_ref());
+
+        assert_eq!(vec![5, 1], written);
+        assert!(archives[0].points().contains(&Point::new(now - 120, 100.0)));
+        assert!(archives[0].points().contains(&Point::new(now - 600, 9.0)));
+        assert!(archives[1].points().contains(&Point::new(now - 600, 5.0)));
+
+        // Everything has a value now so filling again doesn't write anything
+        let written = copy_points(&mut dest, &header, &source, CopyMode::Fill, now).unwrap();
+        assert_eq!(vec![0, 0], written);
+    }
+
+    #[test]
+    fn test_import_values() {
+        let now = 1500000000;
+        let metadata = Metadata::new(AggregationType::Average, 900, 0.5, 2);
+        let header = Header::new(
+            metadata,
+            vec![ArchiveInfo::new(40, 60, 5), ArchiveInfo::new(100, 300, 3)],
+        );
+
+        let points = vec![
+            Point::new(now - 600, 1.0),
+            Point::new(now - 119, 3.0),
+            Point::new(now - 478, 2.0),
+            Point::new(now - 90, 4.0),
+            Point::new(now - 60, 5.0),
+        ];
+
+        // Points older than the highest precision archive are still written
+        // to the lower precision one, which only aggregates the intervals
+        // that were imported.
+        assert_eq!(
+            vec![
+                vec![(now - 120, 4.0), (now - 60, 5.0)],
+                vec![(now - 600, 1.5), (now - 300, 4.5)],
+            ],
+            import_values(&points, &header, now)
+        );
+    }
+
+    #[test]
+    fn test_create_temp_file_skips_existing() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.wsp");
+        let next = TEMP_FILE_COUNTER.load(Ordering::Relaxed);
+        let stale: Vec<_> = (next..next + 3)
+            .map(|i| {
+                dir.path()
+                    .join(format!("test.wsp.{}.{}.tmp", process::id(), i))
+            })
+            .collect();
+        for p in &stale {
+            fs::write(p, b"stale").unwrap();
+        }
+
+        let (tmp, _file) = create_temp_file(&path).unwrap();
+
+        assert_eq!(dir.path(), tmp.parent().unwrap());
+        assert!(!stale.contains(&tmp));
+        assert!(tmp.exists());
+        for p in &stale {
+            assert_eq!(b"stale".to_vec(), fs::read(p).unwrap());
+        }
+    }
+}
@@ -1,9 +1,27 @@
 extern crate chrono;
+extern crate fs2;
 extern crate memento;
+extern crate tempfile;
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use chrono::{TimeZone, Utc};
-use memento::types::AggregationType;
-use memento::{FetchRequest, MementoFileReader};
+use fs2::FileExt;
+use memento::carbon::{CarbonServer, MetricStore};
+use memento::check::Problem;
+use memento::diff::{HeaderDiff, PointDiff};
+use memento::errors::ErrorKind;
+use memento::import::{read_csv, read_graphite_json, ImportMode};
+use memento::repair::Repair;
+use memento::types::{AggregationType, ArchiveInfo, Header, HeaderBuilder, Metadata, Point};
+use memento::{FetchRequest, LockMode, MementoFileReader, MementoFileWriter};
+use tempfile::TempDir;
 
 #[test]
 fn test_memento_file_reader_read_header() {
@@ -24,6 +42,20 @@ fn test_memento_file_reader_read_database() {
     assert_eq!(5, database.data().archives().len());
 }
 
+#[test]
+fn test_memento_file_reader_view_archives() {
+    let reader = MementoFileReader::new();
+    let database = reader.read_database("tests/upper_01.wsp").unwrap();
+    let archives = reader
+        .view_archives("tests/upper_01.wsp", |header, views| {
+            assert_eq!(database.header(), header);
+            Ok(views.iter().map(|v| v.to_archive()).collect::<Vec<_>>())
+        })
+        .unwrap();
+
+    assert_eq!(database.data().archives(), &archives[..]);
+}
+
 #[test]
 fn test_memento_file_reader_read() {
     let from = Utc.timestamp(1502089980, 0);
@@ -34,8 +66,904 @@ fn test_memento_file_reader_read() {
     let reader = MementoFileReader::new();
     let response = reader.read("tests/upper_01.wsp", &request).unwrap();
     let info = response.archive();
-    let points = response.points();
+    let values = response.values();
 
+    // Expected values are from whisper.fetch() for the same file and times
     assert_eq!(300, info.seconds_per_point());
-    assert_eq!(566, points.len());
+    assert_eq!(1502090100, response.from());
+    assert_eq!(1502259900, response.until());
+    assert_eq!(566, values.len());
+    assert_eq!(566, response.points().len());
+    assert_eq!(&[Some(3696.0), Some(9734.0), Some(2225.0)], &values[..3]);
+    assert_eq!(&[Some(2946.0), Some(2710.0), Some(6685.0)], &values[563..]);
+}
+
+#[test]
+fn test_memento_file_reader_read_many() {
+    let from = Utc.timestamp(1502089980, 0);
+    let until = Utc.timestamp(1502259660, 0);
+    let now = Utc.timestamp(1502864800, 0);
+    let request = FetchRequest::new(from, until, now);
+    let paths = vec![
+        "tests/upper_01.wsp",
+        "tests/zero_file.bin",
+        "tests/missing.wsp",
+        "tests/upper_01.wsp",
+    ];
+
+    let reader = MementoFileReader::new().with_threads(2);
+    let results = reader.read_many(&paths, &request);
+    let expected = reader.read("tests/upper_01.wsp", &request).unwrap();
+
+    assert_eq!(4, results.len());
+    assert_eq!(&expected, results[0].as_ref().unwrap());
+    assert!(results[1].is_err());
+    assert_eq!(ErrorKind::IoError, results[2].as_ref().unwrap_err().kind());
+    assert_eq!(&expected, results[3].as_ref().unwrap());
+}
+
+#[test]
+fn test_memento_file_reader_error_context() {
+    let reader = MementoFileReader::new();
+    let err = reader.read_header("tests/zero_file.bin").unwrap_err();
+
+    assert_eq!(Some(Path::new("tests/zero_file.bin")), err.path());
+    assert_eq!(Some(0), err.offset());
+    assert!(err.to_string().contains("tests/zero_file.bin"));
+
+    let err = reader.read_database("tests/missing.wsp").unwrap_err();
+    assert_eq!(ErrorKind::IoError, err.kind());
+    assert_eq!(Some(Path::new("tests/missing.wsp")), err.path());
+}
+
+#[test]
+fn test_memento_file_reader_check_tree() {
+    let dir = TempDir::new().unwrap();
+    let nested = dir.path().join("nested");
+    fs::create_dir(&nested).unwrap();
+
+    let bytes = fs::read("tests/upper_01.wsp").unwrap();
+    fs::write(dir.path().join("upper_01.wsp"), &bytes).unwrap();
+    fs::write(nested.join("truncated.wsp"), &bytes[..bytes.len() - 6]).unwrap();
+    fs::write(nested.join("README"), b"not a database").unwrap();
+
+    let reader = MementoFileReader::new().with_threads(2);
+    let results = reader.check_tree(dir.path()).unwrap();
+
+    assert_eq!(2, results.len());
+    assert_eq!(nested.join("truncated.wsp"), results[0].0);
+    assert_eq!(
+        &[Problem::FileSize {
+            expected: bytes.len() as u64,
+            actual: bytes.len() as u64 - 6,
+        }],
+        results[0].1.as_ref().unwrap().problems()
+    );
+    assert_eq!(dir.path().join("upper_01.wsp"), results[1].0);
+    assert!(results[1].1.as_ref().unwrap().is_ok());
+}
+
+#[test]
+fn test_memento_file_reader_check_empty_file() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("empty.wsp");
+    File::create(&path).unwrap();
+
+    let reader = MementoFileReader::new();
+    let report = reader.check(&path).unwrap();
+
+    assert_eq!(
+        &[Problem::TruncatedHeader {
+            expected: 16,
+            actual: 0,
+        }],
+        report.problems()
+    );
+}
+
+#[test]
+fn test_memento_file_writer_create() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("create.wsp");
+    let metadata = Metadata::new(AggregationType::Last, 0, 0.5, 0);
+    let info1 = ArchiveInfo::new(0, 10, 8640);
+    let info2 = ArchiveInfo::new(0, 60, 10080);
+    let header = Header::new(metadata, vec![info1, info2]);
+
+    let writer = MementoFileWriter::new();
+    let created = writer.create(&path, &header).unwrap();
+
+    let reader = MementoFileReader::new();
+    let database = reader.read_database(&path).unwrap();
+
+    assert_eq!(&created, database.header());
+    assert_eq!(604800, created.metadata().max_retention());
+    assert_eq!(created.file_size(), fs::metadata(&path).unwrap().len());
+}
+
+#[test]
+fn test_memento_file_writer_create_existing_file() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("existing.wsp");
+    fs::write(&path, b"not a database").unwrap();
+
+    let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 0);
+    let header = Header::new(metadata, vec![ArchiveInfo::new(0, 60, 1440)]);
+
+    let writer = MementoFileWriter::new();
+    let res = writer.create(&path, &header);
+
+    assert_eq!(ErrorKind::IoError, res.unwrap_err().kind());
+    assert_eq!(b"not a database", &fs::read(&path).unwrap()[..]);
+}
+
+#[test]
+fn test_memento_file_writer_update() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("update.wsp");
+    let metadata = Metadata::new(AggregationType::Average, 0, 0.0, 0);
+    let info1 = ArchiveInfo::new(0, 60, 1440);
+    let info2 = ArchiveInfo::new(0, 300, 2016);
+    let header = Header::new(metadata, vec![info1, info2]);
+    let now = Utc::now();
+
+    let writer = MementoFileWriter::new();
+    writer.create(&path, &header).unwrap();
+    writer.update(&path, 42.0, now).unwrap();
+
+    let reader = MementoFileReader::new();
+    let database = reader.read_database(&path).unwrap();
+    let archives = database.data().archives();
+    let timestamp = now.timestamp() as u32;
+
+    assert_eq!(
+        timestamp - timestamp % 60,
+        archives[0].points()[0].timestamp()
+    );
+    assert_eq!(42.0, archives[0].points()[0].value());
+    assert_eq!(
+        timestamp - timestamp % 300,
+        archives[1].points()[0].timestamp()
+    );
+    assert_eq!(42.0, archives[1].points()[0].value());
+}
+
+#[test]
+fn test_memento_file_writer_update_many() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("update_many.wsp");
+    let metadata = Metadata::new(AggregationType::Sum, 0, 0.5, 0);
+    let info1 = ArchiveInfo::new(0, 60, 1440);
+    let info2 = ArchiveInfo::new(0, 300, 2016);
+    let header = Header::new(metadata, vec![info1, info2]);
+
+    let now = Utc::now().timestamp() as u32;
+    let start = now - now % 300 - 300;
+    let points: Vec<Point> = (0..5)
+        .map(|i| Point::new(start + i * 60, f64::from(i)))
+        .collect();
+
+    let writer = MementoFileWriter::new();
+    writer.create(&path, &header).unwrap();
+    writer.update_many(&path, &points).unwrap();
+
+    let reader = MementoFileReader::new();
+    let database = reader.read_database(&path).unwrap();
+    let archives = database.data().archives();
+
+    assert_eq!(&points[..], &archives[0].points()[0..5]);
+    assert_eq!(Point::new(start, 10.0), archives[1].points()[0]);
+}
+
+#[test]
+fn test_memento_file_writer_create_invalid_archives() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("invalid.wsp");
+    let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 0);
+    let info1 = ArchiveInfo::new(0, 60, 1440);
+    let info2 = ArchiveInfo::new(0, 90, 10080);
+    let header = Header::new(metadata, vec![info1, info2]);
+
+    let writer = MementoFileWriter::new();
+    let res = writer.create(&path, &header);
+
+    assert_eq!(ErrorKind::UnevenArchivePrecision, res.unwrap_err().kind());
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_memento_file_reader_lock_unavailable() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("locked.wsp");
+    fs::copy("tests/upper_01.wsp", &path).unwrap();
+
+    let file = File::open(&path).unwrap();
+    FileExt::lock_exclusive(&file).unwrap();
+
+    let reader = MementoFileReader::new().with_lock(LockMode::NonBlocking);
+    let res = reader.read_header(&path);
+    assert_eq!(ErrorKind::LockUnavailable, res.unwrap_err().kind());
+
+    let reader = MementoFileReader::new();
+    assert!(reader.read_header(&path).is_ok());
+}
+
+#[test]
+fn test_memento_file_writer_lock_timeout() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("locked.wsp");
+    fs::copy("tests/upper_01.wsp", &path).unwrap();
+
+    let file = File::open(&path).unwrap();
+    FileExt::lock_shared(&file).unwrap();
+
+    let timeout = Duration::from_millis(20);
+    let writer = MementoFileWriter::new().with_lock(LockMode::Timeout(timeout));
+    let res = writer.update_many(&path, &[]);
+    assert_eq!(ErrorKind::LockUnavailable, res.unwrap_err().kind());
+
+    let reader = MementoFileReader::new().with_lock(LockMode::NonBlocking);
+    assert!(reader.read_header(&path).is_ok());
+}
+
+#[test]
+fn test_memento_file_writer_set_aggregation() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("aggregation.wsp");
+    let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 0);
+    let header = Header::new(metadata, vec![ArchiveInfo::new(0, 60, 1440)]);
+
+    let writer = MementoFileWriter::new().with_lock(LockMode::Blocking);
+    let created = writer.create(&path, &header).unwrap();
+    let previous = writer.set_aggregation(&path, AggregationType::Sum).unwrap();
+
+    let reader = MementoFileReader::new();
+    let header = reader.read_header(&path).unwrap();
+
+    assert_eq!(AggregationType::Average, previous);
+    assert_eq!(AggregationType::Sum, header.metadata().aggregation());
+    assert_eq!(0.5, header.metadata().x_files_factor());
+    assert_eq!(created.archive_info(), header.archive_info());
+}
+
+#[test]
+fn test_memento_file_writer_set_x_files_factor() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("xff.wsp");
+    let metadata = Metadata::new(AggregationType::Max, 0, 0.5, 0);
+    let header = Header::new(metadata, vec![ArchiveInfo::new(0, 60, 1440)]);
+
+    let writer = MementoFileWriter::new().with_lock(LockMode::Blocking);
+    writer.create(&path, &header).unwrap();
+    let previous = writer.set_x_files_factor(&path, 0.25).unwrap();
+
+    let reader = MementoFileReader::new();
+    let header = reader.read_header(&path).unwrap();
+
+    assert_eq!(0.5, previous);
+    assert_eq!(0.25, header.metadata().x_files_factor());
+    assert_eq!(AggregationType::Max, header.metadata().aggregation());
+
+    for &val in &[-0.1, 1.5, f32::NAN] {
+        let res = writer.set_x_files_factor(&path, val);
+        assert_eq!(ErrorKind::InvalidXFilesFactor, res.unwrap_err().kind());
+    }
+
+    let header = reader.read_header(&path).unwrap();
+    assert_eq!(0.25, header.metadata().x_files_factor());
+}
+
+fn file_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_memento_file_writer_resize() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("resize.wsp");
+    let metadata = Metadata::new(AggregationType::Sum, 0, 0.0, 0);
+    let header = Header::new(metadata, vec![ArchiveInfo::new(0, 60, 1440)]);
+    let now = Utc::now().timestamp() as u32;
+    let start = now - now % 300 - 600;
+
+    let writer = MementoFileWriter::new().with_lock(LockMode::Blocking);
+    let original = writer.create(&path, &header).unwrap();
+    let points: Vec<Point> = (0..10)
+        .map(|i| Point::new(start + 60 * i, f64::from(i)))
+        .collect();
+    writer.update_many(&path, &points).unwrap();
+
+    let metadata = Metadata::new(AggregationType::Max, 0, 0.5, 0);
+    let info1 = ArchiveInfo::new(0, 60, 60);
+    let info2 = ArchiveInfo::new(0, 300, 288);
+    let resized = writer
+        .resize(&path, &Header::new(metadata, vec![info2, info1]), true)
+        .unwrap();
+
+    let reader = MementoFileReader::new();
+    let database = reader.read_database(&path).unwrap();
+    let archives = database.data().archives();
+
+    assert_eq!(&resized, database.header());
+    assert_eq!(60, resized.archive_info()[0].seconds_per_point());
+    assert_eq!(AggregationType::Max, resized.metadata().aggregation());
+    assert_eq!(resized.file_size(), fs::metadata(&path).unwrap().len());
+    assert_eq!(vec!["resize.wsp", "resize.wsp.bak"], file_names(dir.path()));
+
+    assert!(archives[0].points().contains(&Point::new(start + 60, 1.0)));
+    assert!(archives[1].points().contains(&Point::new(start, 10.0)));
+    assert!(archives[1]
+        .points()
+        .contains(&Point::new(start + 300, 35.0)));
+
+    let backup = reader
+        .read_header(dir.path().join("resize.wsp.bak"))
+        .unwrap();
+    assert_eq!(original, backup);
+}
+
+#[test]
+fn test_memento_file_writer_resize_invalid_archives() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("resize.wsp");
+    let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 0);
+    let header = Header::new(metadata.clone(), vec![ArchiveInfo::new(0, 60, 1440)]);
+
+    let writer = MementoFileWriter::new();
+    let original = writer.create(&path, &header).unwrap();
+
+    let infos = vec![
+        ArchiveInfo::new(0, 60, 1440),
+        ArchiveInfo::new(0, 60, 10080),
+    ];
+    let res = writer.resize(&path, &Header::new(metadata, infos), true);
+
+    let reader = MementoFileReader::new();
+    assert_eq!(
+        ErrorKind::DuplicateArchivePrecision,
+        res.unwrap_err().kind()
+    );
+    assert_eq!(original, reader.read_header(&path).unwrap());
+    assert_eq!(vec!["resize.wsp"], file_names(dir.path()));
+}
+
+#[test]
+fn test_memento_file_writer_merge_and_fill() {
+    let dir = TempDir::new().unwrap();
+    let source = dir.path().join("source.wsp");
+    let dest = dir.path().join("dest.wsp");
+    let now = Utc::now().timestamp() as u32;
+    let start = now - now % 60 - 600;
+
+    let writer = MementoFileWriter::new().with_lock(LockMode::Blocking);
+    let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 0);
+    let header = Header::new(metadata.clone(), vec![ArchiveInfo::new(0, 60, 1440)]);
+    writer.create(&source, &header).unwrap();
+    let points: Vec<Point> = (0..5)
+        .map(|i| Point::new(start + 60 * i, f64::from(i)))
+        .collect();
+    writer.update_many(&source, &points).unwrap();
+
+    let infos = vec![ArchiveInfo::new(0, 60, 60), ArchiveInfo::new(0, 3600, 24)];
+    writer.create(&dest, &Header::new(metadata, infos)).unwrap();
+    writer
+        .update(&dest, 42.0, Utc.timestamp(i64::from(start), 0))
+        .unwrap();
+
+    let written = writer.fill(&source, &dest).unwrap();
+    assert_eq!(4, written[0]);
+
+    let reader = MementoFileReader::new();
+    let database = reader.read_database(&dest).unwrap();
+    let points = database.data().archives()[0].points();
+    assert!(points.contains(&Point::new(start, 42.0)));
+    assert!(points.contains(&Point::new(start + 240, 4.0)));
+
+    let written = writer.merge(&source, &dest).unwrap();
+    assert_eq!(5, written[0]);
+
+    let database = reader.read_database(&dest).unwrap();
+    let points = database.data().archives()[0].points();
+    assert!(points.contains(&Point::new(start, 0.0)));
+    assert!(points.contains(&Point::new(start + 240, 4.0)));
+}
+
+#[test]
+fn test_memento_file_writer_import() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("import.wsp");
+    let now = Utc::now().timestamp() as u32;
+    let start = now - now % 300 - 600;
+
+    let csv: String = (0..5)
+        .map(|i| format!("{},{}\n", start + 60 * i, i))
+        .collect();
+    let points = read_csv(format!("timestamp,value\n{}", csv).as_bytes()).unwrap();
+
+    let writer = MementoFileWriter::new().with_lock(LockMode::Blocking);
+    let metadata = Metadata::new(AggregationType::Sum, 0, 0.0, 0);
+    let infos = vec![ArchiveInfo::new(0, 60, 60), ArchiveInfo::new(0, 300, 24)];
+    let header = Header::new(metadata, infos);
+
+    let written = writer
+        .import(&path, &points, ImportMode::Skip, Some(&header))
+        .unwrap();
+    assert_eq!(Some(vec![5, 1]), written);
+
+    let json = format!(
+        r#"[{{"target": "foo", "datapoints": [[42, {}], [null, {}], [43, {}]]}}]"#,
+        start,
+        start + 60,
+        start + 300
+    );
+    let points = read_graphite_json(json.as_bytes()).unwrap();
+
+    let written = writer
+        .import(&path, &points, ImportMode::Skip, Some(&header))
+        .unwrap();
+    assert_eq!(None, written);
+
+    let written = writer
+        .import(&path, &points, ImportMode::Fill, None)
+        .unwrap();
+    assert_eq!(Some(vec![1, 1]), written);
+
+    let reader = MementoFileReader::new();
+    let database = reader.read_database(&path).unwrap();
+    let archives = database.data().archives();
+    assert!(archives[0].points().contains(&Point::new(start, 0.0)));
+    assert!(archives[0]
+        .points()
+        .contains(&Point::new(start + 300, 43.0)));
+    assert!(archives[1].points().contains(&Point::new(start, 10.0)));
+
+    let written = writer
+        .import(&path, &points, ImportMode::Overwrite, None)
+        .unwrap();
+    assert_eq!(Some(vec![2, 2]), written);
+
+    let database = reader.read_database(&path).unwrap();
+    let archives = database.data().archives();
+    assert!(archives[0].points().contains(&Point::new(start, 42.0)));
+    assert!(archives[1].points().contains(&Point::new(start, 42.0)));
+}
+
+#[test]
+fn test_memento_file_writer_import_unaligned() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("import.wsp");
+    let now = Utc::now().timestamp() as u32;
+    let start = now - now % 300 - 600;
+
+    let points = vec![
+        Point::new(start + 1, 1.0),
+        Point::new(start + 62, 5.0),
+        Point::new(start + 119, 2.0),
+        Point::new(start + 181, 3.0),
+    ];
+
+    let writer = MementoFileWriter::new();
+    let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 0);
+    let infos = vec![ArchiveInfo::new(0, 60, 60), ArchiveInfo::new(0, 300, 24)];
+    let header = Header::new(metadata, infos);
+
+    let written = writer
+        .import(&path, &points, ImportMode::Overwrite, Some(&header))
+        .unwrap();
+    assert_eq!(Some(vec![3, 1]), written);
+
+    let reader = MementoFileReader::new();
+    let database = reader.read_database(&path).unwrap();
+    let archives = database.data().archives();
+    assert!(archives[0].points().contains(&Point::new(start, 1.0)));
+    assert!(archives[0].points().contains(&Point::new(start + 60, 2.0)));
+    assert!(archives[0].points().contains(&Point::new(start + 180, 3.0)));
+    assert!(archives[1].points().contains(&Point::new(start, 2.0)));
+}
+
+#[test]
+fn test_memento_file_writer_import_coarse() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("import.wsp");
+    let now = Utc::now().timestamp() as u32;
+    let start = now - now % 3600 - 20 * 86400;
+
+    // Hourly points older than the per minute archive only fit in the hourly
+    // archive and are written there despite the x-files-factor.
+    let points: Vec<Point> = (0..240)
+        .map(|i| Point::new(start + 3600 * i, f64::from(i)))
+        .collect();
+
+    let writer = MementoFileWriter::new();
+    let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 0);
+    let infos = vec![
+        ArchiveInfo::new(0, 60, 1440),
+        ArchiveInfo::new(0, 3600, 720),
+    ];
+    let header = Header::new(metadata, infos);
+
+    let written = writer
+        .import(&path, &points, ImportMode::Overwrite, Some(&header))
+        .unwrap();
+    assert_eq!(Some(vec![0, 240]), written);
+
+    let reader = MementoFileReader::new();
+    let database = reader.read_database(&path).unwrap();
+    let archive = &database.data().archives()[1];
+    assert!(archive.points().contains(&Point::new(start, 0.0)));
+    assert!(archive
+        .points()
+        .contains(&Point::new(start + 3600 * 239, 239.0)));
+}
+
+#[test]
+fn test_memento_file_writer_import_missing_file() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("import.wsp");
+
+    let writer = MementoFileWriter::new();
+    let res = writer.import(&path, &[], ImportMode::Overwrite, None);
+
+    assert_eq!(ErrorKind::IoError, res.unwrap_err().kind());
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_memento_file_writer_repair() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("repair.wsp");
+    let now = Utc::now().timestamp() as u32;
+    let start = now - now % 60 - 600;
+
+    let writer = MementoFileWriter::new().with_lock(LockMode::Blocking);
+    let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 0);
+    let header = Header::new(metadata, vec![ArchiveInfo::new(0, 60, 1440)]);
+    let created = writer.create(&path, &header).unwrap();
+    let points: Vec<Point> = (0..5)
+        .map(|i| Point::new(start + 60 * i, f64::from(i)))
+        .collect();
+    writer.update_many(&path, &points).unwrap();
+
+    assert!(writer.repair(&path, true).unwrap().is_unchanged());
+    assert!(!dir.path().join("repair.wsp.bak").exists());
+
+    // Garbage timestamp in the third slot and the end of the file missing
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[28 + 24..28 + 28].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+    bytes.truncate(bytes.len() - 100);
+    fs::write(&path, &bytes).unwrap();
+
+    let report = writer.repair(&path, true).unwrap();
+    assert_eq!(
+        &[
+            Repair::Extended {
+                from: created.file_size() - 100,
+                to: created.file_size(),
+            },
+            Repair::ClearedPoint {
+                archive: 0,
+                index: 2,
+                point: Point::new(0x12345678, 2.0),
+            },
+        ],
+        report.repairs()
+    );
+    assert_eq!(vec!["repair.wsp", "repair.wsp.bak"], file_names(dir.path()));
+    assert_eq!(
+        bytes.len() as u64,
+        fs::metadata(dir.path().join("repair.wsp.bak"))
+            .unwrap()
+            .len()
+    );
+
+    let reader = MementoFileReader::new();
+    assert!(reader.check(&path).unwrap().is_ok());
+    let database = reader.read_database(&path).unwrap();
+    let points = database.data().archives()[0].points();
+    assert_eq!(&Point::default(), &points[2]);
+    assert_eq!(&Point::new(start + 240, 4.0), &points[4]);
+}
+
+#[test]
+fn test_memento_file_writer_repair_base_marker() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("repair.wsp");
+    let header = HeaderBuilder::new()
+        .with_x_files_factor(0.0)
+        .with_archives(vec![
+            ArchiveInfo::new(0, 60, 20),
+            ArchiveInfo::new(0, 300, 12),
+        ])
+        .build();
+    let now = Utc::now().timestamp() as u32;
+    let start = now - now % 300 - 600;
+
+    let writer = MementoFileWriter::new();
+    writer.create(&path, &header).unwrap();
+    let points: Vec<Point> = (0..5)
+        .map(|i| Point::new(start + 60 * i, f64::from(i + 1)))
+        .collect();
+    writer.update_many(&path, &points).unwrap();
+
+    // Garbage timestamp in the first slot of the first archive
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[40..44].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+    fs::write(&path, &bytes).unwrap();
+
+    let report = writer.repair(&path, false).unwrap();
+    assert_eq!(
+        &[Repair::RewroteBase {
+            archive: 0,
+            point: Point::new(0x12345678, 1.0),
+            timestamp: start - 59,
+        }],
+        report.repairs()
+    );
+
+    let reader = MementoFileReader::new();
+    assert!(reader.check(&path).unwrap().is_ok());
+
+    // The marker locates the other slots but is never read as a value
+    let request = FetchRequest::new(
+        Utc.timestamp(i64::from(start) - 60, 0),
+        Utc.timestamp(i64::from(start) + 240, 0),
+        Utc.timestamp(i64::from(now), 0),
+    );
+    let response = reader.read(&path, &request).unwrap();
+    assert_eq!(start, response.from());
+    assert_eq!(
+        &[None, Some(2.0), Some(3.0), Some(4.0), Some(5.0)],
+        response.values()
+    );
+
+    // Propagation to the lower precision archive ignores it as well
+    writer
+        .update(&path, 10.0, Utc.timestamp(i64::from(start) + 60, 0))
+        .unwrap();
+    let database = reader.read_database(&path).unwrap();
+    assert!(database.data().archives()[1]
+        .points()
+        .contains(&Point::new(start, 5.5)));
+}
+
+#[test]
+fn test_memento_file_writer_repair_corrupt_header() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("repair.wsp");
+    fs::write(&path, [0u8; 20]).unwrap();
+
+    let writer = MementoFileWriter::new();
+    let res = writer.repair(&path, false);
+
+    assert_eq!(ErrorKind::CorruptDatabase, res.unwrap_err().kind());
+    assert_eq!(20, fs::metadata(&path).unwrap().len());
+    assert_eq!(vec!["repair.wsp"], file_names(dir.path()));
+}
+
+#[test]
+fn test_memento_file_reader_diff() {
+    let dir = TempDir::new().unwrap();
+    let first = dir.path().join("first.wsp");
+    let second = dir.path().join("second.wsp");
+    let now = Utc::now();
+
+    let writer = MementoFileWriter::new();
+    let metadata = Metadata::new(AggregationType::Average, 0, 0.5, 0);
+    writer
+        .create(
+            &first,
+            &Header::new(metadata, vec![ArchiveInfo::new(0, 60, 1440)]),
+        )
+        .unwrap();
+    let metadata = Metadata::new(AggregationType::Sum, 0, 0.5, 0);
+    writer
+        .create(
+            &second,
+            &Header::new(metadata, vec![ArchiveInfo::new(0, 60, 1440)]),
+        )
+        .unwrap();
+    writer.update(&first, 1.0, now).unwrap();
+    writer.update(&second, 2.0, now).unwrap();
+
+    let reader = MementoFileReader::new();
+    let diff = reader.diff(&first, &second).unwrap();
+    let timestamp = now.timestamp() as u32;
+
+    assert_eq!(
+        &[HeaderDiff::Aggregation(
+            AggregationType::Average,
+            AggregationType::Sum
+        )],
+        diff.header()
+    );
+    assert_eq!(
+        &[PointDiff::new(
+            0,
+            timestamp - timestamp % 60,
+            Some(1.0),
+            Some(2.0)
+        )],
+        diff.points()
+    );
+    assert!(reader.diff(&first, &first).unwrap().is_empty());
+}
+
+/// Read the points of the first archive of a file once it has at least
+/// `count` points, waiting for a server on another thread to write them.
+fn wait_for_points(reader: &MementoFileReader, path: &Path, count: usize) -> Vec<Point> {
+    for _ in 0..100 {
+        if let Ok(database) = reader.read_database(path) {
+            let points: Vec<Point> = database.data().archives()[0]
+                .points()
+                .iter()
+                .filter(|p| p.timestamp() != 0)
+                .cloned()
+                .collect();
+
+            if points.len() >= count {
+                return points;
+            }
+        }
+
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    panic!("timed out waiting for points in {}", path.display());
+}
+
+#[test]
+fn test_carbon_server() {
+    let dir = TempDir::new().unwrap();
+    let header = HeaderBuilder::new()
+        .with_archives(vec![ArchiveInfo::new(0, 60, 1440)])
+        .build();
+    let store = MetricStore::new(dir.path(), &header).unwrap();
+
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let handler_errors = Arc::clone(&errors);
+    let mut server = CarbonServer::new(store)
+        .with_error_handler(move |e| handler_errors.lock().unwrap().push(e.to_string()));
+    let tcp = server.listen_tcp("127.0.0.1:0").unwrap();
+    let udp = server.listen_udp("127.0.0.1:0").unwrap();
+    thread::spawn(move || server.run());
+
+    let now = Utc::now().timestamp() as u32;
+    let now = now - now % 60;
+
+    let mut stream = TcpStream::connect(tcp).unwrap();
+    write!(
+        stream,
+        "foo.bar 1.5 {}\nfoo.bar 2.5 {}\nnot a valid line\n",
+        now - 60,
+        now
+    )
+    .unwrap();
+    drop(stream);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .send_to(format!("baz 3 {}\n", now).as_bytes(), udp)
+        .unwrap();
+
+    let reader = MementoFileReader::new();
+    let points = wait_for_points(&reader, &dir.path().join("foo").join("bar.wsp"), 2);
+    assert!(points.contains(&Point::new(now - 60, 1.5)));
+    assert!(points.contains(&Point::new(now, 2.5)));
+
+    let points = wait_for_points(&reader, &dir.path().join("baz.wsp"), 1);
+    assert_eq!(vec![Point::new(now, 3.0)], points);
+
+    let errors = errors.lock().unwrap();
+    assert_eq!(1, errors.len());
+    assert!(errors[0].contains("not a valid line"));
+}
+
+#[test]
+fn test_carbon_server_line_too_long() {
+    let dir = TempDir::new().unwrap();
+    let header = HeaderBuilder::new()
+        .with_archives(vec![ArchiveInfo::new(0, 60, 1440)])
+        .build();
+    let store = MetricStore::new(dir.path(), &header).unwrap();
+
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let handler_errors = Arc::clone(&errors);
+    let mut server = CarbonServer::new(store)
+        .with_error_handler(move |e| handler_errors.lock().unwrap().push(e.to_string()));
+    let tcp = server.listen_tcp("127.0.0.1:0").unwrap();
+    thread::spawn(move || server.run());
+
+    let now = Utc::now().timestamp() as u32;
+    let now = now - now % 60;
+
+    let mut stream = TcpStream::connect(tcp).unwrap();
+    writeln!(stream, "foo 1 {}", now).unwrap();
+    stream.flush().unwrap();
+    // The server may close the connection before everything has been sent
+    let _ = stream.write_all(&vec![b'a'; 100_000]);
+
+    // The connection is closed once the line is too long, and is reset
+    // rather than shut down cleanly if data was left unread.
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut buf = [0u8; 16];
+    match stream.read(&mut buf) {
+        Ok(len) => assert_eq!(0, len),
+        Err(e) => assert_eq!(io::ErrorKind::ConnectionReset, e.kind()),
+    }
+
+    let reader = MementoFileReader::new();
+    let points = wait_for_points(&reader, &dir.path().join("foo.wsp"), 1);
+    assert_eq!(vec![Point::new(now, 1.0)], points);
+
+    let errors = errors.lock().unwrap();
+    assert_eq!(1, errors.len());
+    assert!(errors[0].contains("too long"));
+}
+
+#[test]
+fn test_carbon_server_max_connections() {
+    let dir = TempDir::new().unwrap();
+    let header = HeaderBuilder::new()
+        .with_archives(vec![ArchiveInfo::new(0, 60, 1440)])
+        .build();
+    let store = MetricStore::new(dir.path(), &header).unwrap();
+
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let handler_errors = Arc::clone(&errors);
+    let mut server = CarbonServer::new(store)
+        .with_max_connections(1)
+        .with_error_handler(move |e| handler_errors.lock().unwrap().push(e.to_string()));
+    let tcp = server.listen_tcp("127.0.0.1:0").unwrap();
+    thread::spawn(move || server.run());
+
+    let now = Utc::now().timestamp() as u32;
+    let now = now - now % 60;
+
+    let mut first = TcpStream::connect(tcp).unwrap();
+    writeln!(first, "foo 1 {}", now).unwrap();
+    first.flush().unwrap();
+
+    let reader = MementoFileReader::new();
+    let points = wait_for_points(&reader, &dir.path().join("foo.wsp"), 1);
+    assert_eq!(vec![Point::new(now, 1.0)], points);
+
+    // The first connection is still open so the second is closed
+    let mut second = TcpStream::connect(tcp).unwrap();
+    second
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut buf = [0u8; 16];
+    match second.read(&mut buf) {
+        Ok(len) => assert_eq!(0, len),
+        Err(e) => assert_eq!(io::ErrorKind::ConnectionReset, e.kind()),
+    }
+
+    // Once the first connection is closed (which the server may take a
+    // moment to notice) another one is accepted
+    drop(first);
+    let path = dir.path().join("bar.wsp");
+    for _ in 0..100 {
+        let mut third = TcpStream::connect(tcp).unwrap();
+        let _ = writeln!(third, "bar 2 {}", now);
+        drop(third);
+
+        thread::sleep(Duration::from_millis(50));
+        if path.exists() {
+            break;
+        }
+    }
+
+    let points = wait_for_points(&reader, &path, 1);
+    assert_eq!(vec![Point::new(now, 2.0)], points);
+
+    let errors = errors.lock().unwrap();
+    assert!(!errors.is_empty());
+    assert!(errors.iter().all(|e| e.contains("too many connections")));
 }